@@ -0,0 +1,134 @@
+//! `--calibrate`: measuring where each axis rests and how far it goes, and
+//! saving that to the config as `axis_calibration`.
+
+use eyre::{eyre, Result, WrapErr};
+use gamepad2midi::names;
+use gamepad2midi::Calibration;
+use gilrs::{Axis, GamepadId, Gilrs};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const AXES: [Axis; 6] = [
+    Axis::LeftStickX,
+    Axis::LeftStickY,
+    Axis::LeftZ,
+    Axis::RightStickX,
+    Axis::RightStickY,
+    Axis::RightZ,
+];
+
+const REST: Duration = Duration::from_secs(3);
+const MOVE: Duration = Duration::from_secs(8);
+
+/// Axes that travel less than this are taken to be ones that weren't moved.
+const MIN_TRAVEL: f32 = 0.2;
+
+/// Walks the user through leaving the axes alone and then moving them as far
+/// as they go, and works out a calibration for each axis that moved.
+pub fn run(
+    gilrs: &mut Gilrs,
+    wanted: impl Fn(GamepadId, &str) -> bool,
+) -> HashMap<Axis, Calibration> {
+    println!(
+        "Leave the sticks and triggers alone for {} seconds...",
+        REST.as_secs()
+    );
+    let mut rest: HashMap<Axis, (f32, u32)> = HashMap::new();
+    sample(gilrs, &wanted, REST, |axis, pos| {
+        let (sum, n) = rest.entry(axis).or_default();
+        *sum += pos;
+        *n += 1;
+    });
+    println!(
+        "Now move each stick all the way round and pull each trigger all the way, for {} seconds...",
+        MOVE.as_secs()
+    );
+    let mut travel: HashMap<Axis, (f32, f32)> = HashMap::new();
+    sample(gilrs, &wanted, MOVE, |axis, pos| {
+        let (min, max) = travel.entry(axis).or_insert((pos, pos));
+        *min = min.min(pos);
+        *max = max.max(pos);
+    });
+
+    let mut found = HashMap::new();
+    for axis in AXES.iter() {
+        let name = names::axis_name(*axis);
+        let (min, max) = match travel.get(axis) {
+            Some((min, max)) if max - min >= MIN_TRAVEL => (*min, *max),
+            _ => {
+                println!("  {}: didn't move, skipping", name);
+                continue;
+            }
+        };
+        let center = match rest.get(axis) {
+            Some((sum, n)) => sum / *n as f32,
+            None => (min + max) / 2.0,
+        };
+        // Triggers rest at one end of their travel. Stretching from there
+        // would leave them reading 0..1 instead of the -1..1 they read
+        // uncalibrated, so only their range is corrected.
+        let center = if (center - min).min(max - center) < (max - min) / 4.0 {
+            (min + max) / 2.0
+        } else {
+            center
+        };
+        println!("  {}: center {:.3}, {:.3} to {:.3}", name, center, min, max);
+        found.insert(*axis, Calibration { center, min, max });
+    }
+    found
+}
+
+/// Polls the wanted gamepads' axes until `how_long` is up.
+fn sample(
+    gilrs: &mut Gilrs,
+    wanted: &impl Fn(GamepadId, &str) -> bool,
+    how_long: Duration,
+    mut f: impl FnMut(Axis, f32),
+) {
+    let end = Instant::now() + how_long;
+    while Instant::now() < end {
+        // The events themselves don't matter, reading them is what keeps
+        // each gamepad's state up to date.
+        while gilrs.next_event().is_some() {}
+        for (id, gamepad) in gilrs.gamepads() {
+            if !wanted(id, gamepad.name()) {
+                continue;
+            }
+            for axis in AXES.iter() {
+                if let Some(data) = gamepad.axis_data(*axis) {
+                    f(*axis, data.value());
+                }
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Writes `found` into the config at `path`, replacing the calibration of the
+/// axes it has and keeping everything else. Starts a new config holding just
+/// the calibration if there isn't one yet.
+pub fn save(path: &Path, found: &HashMap<Axis, Calibration>) -> Result<()> {
+    if found.is_empty() {
+        return Err(eyre!("none of the axes moved, there's nothing to save"));
+    }
+    let mut doc = if path.exists() {
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("reading config {}", path.display()))?;
+        toml::from_str(&text).wrap_err_with(|| format!("parsing config {}", path.display()))?
+    } else {
+        toml::value::Table::new()
+    };
+    let calibration = doc
+        .entry("axis_calibration")
+        .or_insert_with(|| toml::Value::Table(toml::value::Table::new()))
+        .as_table_mut()
+        .ok_or_else(|| eyre!("axis_calibration in {} isn't a table", path.display()))?;
+    for (axis, c) in found.iter() {
+        calibration.insert(names::axis_name(*axis).into(), toml::Value::try_from(c)?);
+    }
+    std::fs::write(path, toml::to_string(&toml::Value::Table(doc))?)
+        .wrap_err_with(|| format!("writing config {}", path.display()))?;
+    println!("Saved the calibration to {}", path.display());
+    Ok(())
+}
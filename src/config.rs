@@ -0,0 +1,1688 @@
+//! `Config`, what it's read from and what it's checked for.
+
+use crate::names::{
+    self, AxisName, ButtonName, ChannelNumber, ControlNumber, NoteName, ShortFloat,
+};
+use crate::{apply_deadzone, is_midi, is_sysex, lookup, osc, quantize_to_scale};
+use eyre::{eyre, Result, WrapErr};
+use gilrs::{Axis, Button, GamepadId};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use std::collections::{HashMap, HashSet};
+use std::default::Default;
+use std::path::Path;
+use wmidi::{Channel, ControlFunction, ControlValue, MidiMessage, Note, U7};
+
+/// Where NoteOn velocities come from.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum VelocityMode {
+    /// Always `velocity`.
+    Fixed,
+    /// The analog position of the button when it registers as pressed.
+    /// Digital buttons always report full pressure.
+    FromPressure,
+    /// Louder the sooner a button is pressed again after its last press,
+    /// from `velocity` up to 127 within `velocity_speed_window_ms`.
+    FromSpeed,
+}
+
+/// Response curve applied to an axis or analog button before it is scaled to
+/// MIDI. Curves work on the distance from rest, so sticks stay symmetric.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Curve {
+    Linear,
+    /// Fine control near rest, rising quickly towards full deflection.
+    Exponential,
+    /// Rises quickly off rest, fine control near full deflection.
+    Logarithmic,
+    /// `x^gamma`: above 1 behaves like `Exponential`, below 1 like `Logarithmic`.
+    Gamma(f32),
+}
+
+/// The order an arpeggiated chord's notes are played in, lowest note first.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ArpPattern {
+    Up,
+    Down,
+    /// Up then back down, without repeating the top and bottom notes.
+    UpDown,
+}
+
+impl ArpPattern {
+    /// Which of `len` notes to play on `step`.
+    pub(crate) fn index(self, len: usize, step: usize) -> usize {
+        match self {
+            ArpPattern::Up => step % len,
+            ArpPattern::Down => len - 1 - step % len,
+            ArpPattern::UpDown if len < 2 => 0,
+            ArpPattern::UpDown => {
+                let i = step % (2 * len - 2);
+                if i < len {
+                    i
+                } else {
+                    2 * len - 2 - i
+                }
+            }
+        }
+    }
+}
+
+/// What a `program_buttons` entry does when pressed: `"Next"`/`"Prev"` or a
+/// program number.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ProgramButton {
+    Step(ProgramStep),
+    /// Jump straight to this program. Numbers past 127 also send Bank
+    /// Select, 128 programs to a bank.
+    Program(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ProgramStep {
+    /// Step to the next program in the bank, wrapping from 127 to 0.
+    Next,
+    /// Step to the previous program in the bank, wrapping from 0 to 127.
+    Prev,
+}
+
+/// The transport message a `transport_buttons` entry sends.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Transport {
+    /// Play from the top of the song.
+    Start,
+    Stop,
+    /// Play on from wherever it was stopped.
+    Continue,
+}
+
+/// The highest program number a 14-bit Bank Select can reach.
+const MAX_PROGRAM: u32 = 16384 * 128 - 1;
+
+/// The kinds of message an `outputs` entry can ask for.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MessageKind {
+    /// Note on/off and polyphonic aftertouch.
+    Notes,
+    /// CCs (including NRPNs), program changes, channel pressure and pitch bend.
+    Controls,
+    /// Clock, start/stop and SysEx, which have no channel.
+    System,
+}
+
+/// A port that takes some of the messages instead of `output_port_name`.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    /// Found the same way as `output_port_name`.
+    pub port: String,
+    /// Empty takes every kind.
+    #[serde(default)]
+    pub messages: Vec<MessageKind>,
+    /// Empty takes every channel. System messages ignore this.
+    #[serde_as(as = "Vec<ChannelNumber>")]
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+}
+
+impl Route {
+    pub fn takes(&self, mm: &MidiMessage) -> bool {
+        let kind = match mm {
+            MidiMessage::NoteOn(..)
+            | MidiMessage::NoteOff(..)
+            | MidiMessage::PolyphonicKeyPressure(..) => MessageKind::Notes,
+            MidiMessage::ControlChange(..)
+            | MidiMessage::ProgramChange(..)
+            | MidiMessage::ChannelPressure(..)
+            | MidiMessage::PitchBendChange(..) => MessageKind::Controls,
+            _ => MessageKind::System,
+        };
+        if !self.messages.is_empty() && !self.messages.contains(&kind) {
+            return false;
+        }
+        match mm.channel() {
+            Some(channel) => self.channels.is_empty() || self.channels.contains(&channel),
+            None => true,
+        }
+    }
+}
+
+impl Curve {
+    /// Shapes `x` in `0.0..=1.0`.
+    fn apply(self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        match self {
+            Curve::Linear => x,
+            Curve::Exponential => (4.0 * x).exp_m1() / 4.0_f32.exp_m1(),
+            Curve::Logarithmic => (1.0 + 9.0 * x).log10(),
+            Curve::Gamma(gamma) => x.powf(gamma),
+        }
+    }
+
+    /// Shapes a stick position in `-1.0..=1.0`, keeping its sign.
+    fn apply_centered(self, pos: f32) -> f32 {
+        pos.signum() * self.apply(pos.abs())
+    }
+}
+
+/// Where an axis rests and how far it goes each way, as measured by
+/// `--calibrate`. Raw positions are stretched so that `center` reads 0 and
+/// `min`/`max` read -1/1, each side on its own so lopsided sticks come out
+/// even.
+#[serde_as]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    #[serde_as(as = "ShortFloat")]
+    pub center: f32,
+    #[serde_as(as = "ShortFloat")]
+    pub min: f32,
+    #[serde_as(as = "ShortFloat")]
+    pub max: f32,
+}
+
+impl Calibration {
+    fn apply(self, pos: f32) -> f32 {
+        let pos = if pos >= self.center {
+            (pos - self.center) / (self.max - self.center)
+        } else {
+            (pos - self.center) / (self.center - self.min)
+        };
+        pos.clamp(-1.0, 1.0)
+    }
+}
+
+/// One message in a `macros` or `on_connect` sequence: exactly one of
+/// `note`, `cc`, `program`, `bend` and `sysex`.
+#[serde_as]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MacroStep {
+    /// How long after the step before it (or the press) this one goes out.
+    pub delay_ms: u64,
+    /// Where it goes, if not on the channel the button (or for `on_connect`,
+    /// `output_midi_channel`) sends on.
+    #[serde_as(as = "Option<ChannelNumber>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<Channel>,
+    /// A note, stopped `gate_ms` later like a `drum_buttons` hit.
+    #[serde_as(as = "Option<NoteName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<Note>,
+    /// `[cc, value]`.
+    #[serde_as(as = "Option<(ControlNumber, ControlNumber)>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cc: Option<(ControlValue, U7)>,
+    #[serde_as(as = "Option<ControlNumber>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program: Option<U7>,
+    /// A pitch bend, 0..=16383 with 8192 in the middle.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bend: Option<u16>,
+    /// The bytes from the 0xF0 to the 0xF7, as for `sysex_buttons`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sysex: Option<Vec<u8>>,
+}
+
+impl MacroStep {
+    fn kinds(&self) -> usize {
+        self.note.is_some() as usize
+            + self.cc.is_some() as usize
+            + self.program.is_some() as usize
+            + self.bend.is_some() as usize
+            + self.sysex.is_some() as usize
+    }
+}
+
+/// Checks the steps of a `macros` entry (`of` its button) or `on_connect`.
+fn check_steps(section: &str, of: Option<&str>, steps: &[MacroStep]) -> Result<()> {
+    for (i, step) in steps.iter().enumerate() {
+        let which = match of {
+            Some(btn) => format!("step {} of {}", i + 1, btn),
+            None => format!("step {}", i + 1),
+        };
+        if step.kinds() != 1 {
+            return Err(eyre!(
+                "{}: {} needs exactly one of note, cc, program, bend and sysex",
+                section,
+                which
+            ));
+        }
+        if let Some(bend) = step.bend.filter(|b| *b > 16383) {
+            return Err(eyre!(
+                "{}: the bend in {} is {}, the highest is 16383",
+                section,
+                which,
+                bend
+            ));
+        }
+        if !step.sysex.as_deref().is_none_or(is_sysex) {
+            return Err(eyre!(
+                "{}: the sysex in {} must start with 0xF0, end with 0xF7 and have only 7-bit bytes between",
+                section,
+                which
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// A note played while all of a set of buttons are held together.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ComboNote {
+    #[serde_as(as = "Vec<ButtonName>")]
+    pub buttons: Vec<Button>,
+    #[serde_as(as = "NoteName")]
+    pub note: Note,
+}
+
+impl ComboNote {
+    /// Whether every one of this combo's buttons is also in `other`, which
+    /// has more.
+    pub(crate) fn within(&self, other: &ComboNote) -> bool {
+        self.buttons.len() < other.buttons.len()
+            && self.buttons.iter().all(|b| other.buttons.contains(b))
+    }
+}
+
+/// Notes for a button picked by how far an analog button or axis is pushed
+/// when it's pressed. Its travel is split evenly between them, lowest first:
+/// an analog button from released to fully pressed, an axis from -1 to 1.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModifierNotes {
+    #[serde_as(as = "Vec<NoteName>")]
+    pub notes: Vec<Note>,
+    /// What picks the note, one or the other.
+    #[serde_as(as = "Option<ButtonName>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub button: Option<Button>,
+    #[serde_as(as = "Option<AxisName>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub axis: Option<Axis>,
+}
+
+impl ModifierNotes {
+    /// The note for the modifier at `depth`, 0 to 1.
+    pub(crate) fn pick(&self, depth: f32) -> Option<Note> {
+        let n = self.notes.len();
+        let i = (depth.clamp(0.0, 1.0) * n as f32) as usize;
+        self.notes.get(i.min(n.checked_sub(1)?)).copied()
+    }
+}
+
+/// A thumbstick, for mappings that read both of its axes together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+impl Stick {
+    /// The stick `axis` belongs to, and whether it's that stick's X axis.
+    pub(crate) fn of(axis: Axis) -> Option<(Stick, bool)> {
+        match axis {
+            Axis::LeftStickX => Some((Stick::Left, true)),
+            Axis::LeftStickY => Some((Stick::Left, false)),
+            Axis::RightStickX => Some((Stick::Right, true)),
+            Axis::RightStickY => Some((Stick::Right, false)),
+            _ => None,
+        }
+    }
+}
+
+// Sticks and directions are written as map keys, which toml only reads
+// as strings.
+impl std::fmt::Display for Stick {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::str::FromStr for Stick {
+    type Err = eyre::Report;
+    fn from_str(s: &str) -> Result<Stick> {
+        [Stick::Left, Stick::Right]
+            .iter()
+            .copied()
+            .find(|stick| stick.to_string() == s)
+            .ok_or_else(|| eyre!("no stick named {:?}, expected Left or Right", s))
+    }
+}
+
+/// Which way a stick is pushed, up being N.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Direction {
+    N,
+    NE,
+    E,
+    SE,
+    S,
+    SW,
+    W,
+    NW,
+}
+
+impl Direction {
+    /// Counterclockwise from E, so the index times 45 degrees is the angle.
+    const AROUND: [Direction; 8] = [
+        Direction::E,
+        Direction::NE,
+        Direction::N,
+        Direction::NW,
+        Direction::W,
+        Direction::SW,
+        Direction::S,
+        Direction::SE,
+    ];
+
+    fn is_diagonal(self) -> bool {
+        matches!(
+            self,
+            Direction::NE | Direction::SE | Direction::SW | Direction::NW
+        )
+    }
+
+    fn angle(self) -> f32 {
+        let i = Direction::AROUND
+            .iter()
+            .position(|d| *d == self)
+            .unwrap_or(0);
+        i as f32 * std::f32::consts::FRAC_PI_4
+    }
+
+    /// Whether a stick at `(x, y)` points within `slack` radians of the
+    /// zone around this direction, which is 45 degrees wide with
+    /// `diagonals` and 90 without.
+    pub(crate) fn contains(self, x: f32, y: f32, diagonals: bool, slack: f32) -> bool {
+        let width = zone_width(diagonals);
+        let d = (y.atan2(x) - self.angle()).rem_euclid(std::f32::consts::TAU);
+        d.min(std::f32::consts::TAU - d) <= width / 2.0 + slack
+    }
+
+    /// The zone a stick at `(x, y)` points into.
+    pub(crate) fn of(x: f32, y: f32, diagonals: bool) -> Direction {
+        let width = zone_width(diagonals);
+        let zones = (std::f32::consts::TAU / width).round() as i32;
+        let i = ((y.atan2(x) / width).round() as i32).rem_euclid(zones);
+        Direction::AROUND[(i * 8 / zones) as usize]
+    }
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::str::FromStr for Direction {
+    type Err = eyre::Report;
+    fn from_str(s: &str) -> Result<Direction> {
+        Direction::AROUND
+            .iter()
+            .copied()
+            .find(|d| d.to_string() == s)
+            .ok_or_else(|| eyre!("no direction {:?}, expected one of N, NE, E, ..., NW", s))
+    }
+}
+
+fn zone_width(diagonals: bool) -> f32 {
+    if diagonals {
+        std::f32::consts::FRAC_PI_4
+    } else {
+        std::f32::consts::FRAC_PI_2
+    }
+}
+
+/// What pushing a stick into each direction sends, see `Config::stick_zones`.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StickZones {
+    /// How far from center the stick has to be pushed to be in a zone.
+    #[serde_as(as = "ShortFloat")]
+    pub deadzone: f32,
+    /// A note held while the stick is in the zone.
+    #[serde_as(as = "HashMap<DisplayFromStr, NoteName>")]
+    pub notes: HashMap<Direction, Note>,
+    /// `[cc, value]` pairs sent on entering the zone.
+    #[serde_as(as = "HashMap<DisplayFromStr, Vec<(ControlNumber, ControlNumber)>>")]
+    pub ccs: HashMap<Direction, Vec<(ControlValue, U7)>>,
+}
+
+impl Default for StickZones {
+    fn default() -> StickZones {
+        StickZones {
+            deadzone: 0.5,
+            notes: HashMap::new(),
+            ccs: HashMap::new(),
+        }
+    }
+}
+
+impl StickZones {
+    /// With nothing mapped to the diagonals, the four sides split the
+    /// circle between them so no part of it is dead.
+    pub(crate) fn diagonals(&self) -> bool {
+        self.notes
+            .keys()
+            .chain(self.ccs.keys())
+            .any(|d| d.is_diagonal())
+    }
+}
+
+/// Rumbling the gamepads for the notes coming in on a MIDI input, e.g. from
+/// a drum machine or a DAW's metronome.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rumble {
+    /// The input port, found the same way as `output_port_name`. Read once
+    /// at startup.
+    pub port: String,
+    /// How long each note rumbles for, at a strength set by its velocity.
+    pub duration_ms: u32,
+    /// The (low, high) ranges of notes that run the strong and the weak
+    /// motor. With neither set, every note runs both.
+    #[serde_as(as = "Option<(NoteName, NoteName)>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strong_notes: Option<(Note, Note)>,
+    #[serde_as(as = "Option<(NoteName, NoteName)>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weak_notes: Option<(Note, Note)>,
+}
+
+impl Default for Rumble {
+    fn default() -> Rumble {
+        Rumble {
+            port: String::new(),
+            duration_ms: 60,
+            strong_notes: None,
+            weak_notes: None,
+        }
+    }
+}
+
+impl Rumble {
+    /// The motor effects for a NoteOn, if `note` runs either motor.
+    pub fn effects(&self, note: Note, velocity: U7) -> Vec<gilrs::ff::BaseEffect> {
+        use gilrs::ff::{BaseEffect, BaseEffectType, Replay, Ticks};
+        let magnitude = (u8::from(velocity) as u32 * u16::MAX as u32 / 127) as u16;
+        let runs = |range: Option<(Note, Note)>| match range {
+            Some((low, high)) => low <= note && note <= high,
+            None => self.strong_notes.is_none() && self.weak_notes.is_none(),
+        };
+        let kinds = [
+            (self.strong_notes, BaseEffectType::Strong { magnitude }),
+            (self.weak_notes, BaseEffectType::Weak { magnitude }),
+        ];
+        kinds
+            .iter()
+            .filter(|(range, _)| runs(*range))
+            .map(|(_, kind)| BaseEffect {
+                kind: *kind,
+                scheduling: Replay {
+                    play_for: Ticks::from_ms(self.duration_ms),
+                    ..Replay::default()
+                },
+                ..BaseEffect::default()
+            })
+            .collect()
+    }
+}
+
+/// OSC addresses for gamepad events, see `--osc`.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Osc {
+    /// `host:port` to send to, unless `--osc` says otherwise. Neither sends
+    /// no OSC at all. Read once at startup.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Where button and axis positions go, as floats after their curves and
+    /// deadzones. `{id}` is replaced with the gamepad's id and `{name}`
+    /// with the button or axis name.
+    pub button_address: String,
+    pub axis_address: String,
+    /// Addresses for particular buttons and axes, in place of the patterns.
+    #[serde_as(as = "HashMap<ButtonName, _>")]
+    pub buttons: HashMap<Button, String>,
+    #[serde_as(as = "HashMap<AxisName, _>")]
+    pub axes: HashMap<Axis, String>,
+}
+
+impl Default for Osc {
+    fn default() -> Osc {
+        Osc {
+            target: None,
+            button_address: "/gamepad/{id}/button/{name}".to_string(),
+            axis_address: "/gamepad/{id}/axis/{name}".to_string(),
+            buttons: HashMap::new(),
+            axes: HashMap::new(),
+        }
+    }
+}
+
+impl Osc {
+    pub fn button(&self, id: GamepadId, btn: Button) -> String {
+        let pattern = self.buttons.get(&btn).unwrap_or(&self.button_address);
+        osc::address(pattern, id.into(), names::button_name(btn))
+    }
+
+    pub fn axis(&self, id: GamepadId, ax: Axis) -> String {
+        let pattern = self.axes.get(&ax).unwrap_or(&self.axis_address);
+        osc::address(pattern, id.into(), names::axis_name(ax))
+    }
+}
+
+/// The mappings `shift_button` swaps in for the ones at the top level of the
+/// config while it's held.
+#[serde_as]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Layer {
+    #[serde_as(as = "HashMap<ButtonName, NoteName>")]
+    pub keys: HashMap<Button, Note>,
+    #[serde_as(as = "HashMap<ButtonName, Vec<NoteName>>")]
+    pub chords: HashMap<Button, Vec<Note>>,
+    #[serde_as(as = "HashMap<ButtonName, ControlNumber>")]
+    pub analog_button_ccs: HashMap<Button, ControlValue>,
+    #[serde_as(as = "HashMap<AxisName, ControlNumber>")]
+    pub axis_ccs: HashMap<Axis, ControlValue>,
+}
+
+/// A named set of mappings that `profile_combo` can switch to in place of the
+/// ones at the top level of the config.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    #[serde(flatten)]
+    pub layer: Layer,
+}
+
+/// The mappings in effect for a gamepad, see `Config::mapping`.
+#[derive(Clone, Copy)]
+pub(crate) struct Mapping<'a> {
+    pub(crate) keys: &'a HashMap<Button, Note>,
+    pub(crate) chords: &'a HashMap<Button, Vec<Note>>,
+    pub(crate) analog_button_ccs: &'a HashMap<Button, ControlValue>,
+    pub(crate) axis_ccs: &'a HashMap<Axis, ControlValue>,
+}
+
+impl<'a> From<&'a Layer> for Mapping<'a> {
+    fn from(layer: &'a Layer) -> Mapping<'a> {
+        Mapping {
+            keys: &layer.keys,
+            chords: &layer.chords,
+            analog_button_ccs: &layer.analog_button_ccs,
+            axis_ccs: &layer.axis_ccs,
+        }
+    }
+}
+
+impl Mapping<'_> {
+    /// What `btn` plays, from `chords` or failing that `keys`.
+    pub(crate) fn notes(&self, btn: Button) -> Option<Vec<Note>> {
+        match self.chords.get(&btn) {
+            Some(chord) => Some(chord.clone()),
+            None => self.keys.get(&btn).map(|note| vec![*note]),
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub output_port_name: String,
+    pub create_virtual: bool,
+    /// Further ports, each sent whatever it `takes`. A message can go to
+    /// several of them, and only goes to `output_port_name` if none take it.
+    /// Read once at startup: reloading the config doesn't reconnect.
+    pub outputs: Vec<Route>,
+    /// An input port whose messages are sent on as they are, merged in with
+    /// ours, found the same way as `output_port_name`. It can't be one of
+    /// the ports we send to. Read once at startup.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thru_port: Option<String>,
+    /// How long to sleep once the gilrs event queue is empty.
+    pub poll_interval_ms: u64,
+    /// The most gamepad events to handle before running the timed work (the
+    /// clock, arps, smoothing and the rest) again. Unset handles every event
+    /// waiting first, which is lowest latency for the events, but a flood
+    /// of them, like a stick being waggled across several pads, can push
+    /// those timers late. A limit keeps the timers on time, at the cost of
+    /// the events beyond it waiting a pass (without the sleep) for their
+    /// turn.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_events_per_tick: Option<usize>,
+    /// How many times to try opening gamepad input at startup, which can
+    /// fail while the system is still coming up, before giving up. The wait
+    /// between tries starts at `gilrs_init_retry_ms` and doubles each time.
+    pub gilrs_init_attempts: u32,
+    pub gilrs_init_retry_ms: u64,
+    #[serde_as(as = "ChannelNumber")]
+    pub output_midi_channel: Channel,
+    /// Channels handed out to gamepads in the order they connect. Empty sends
+    /// every gamepad on `output_midi_channel`, which is also used once the
+    /// list runs out.
+    #[serde_as(as = "Vec<ChannelNumber>")]
+    pub gamepad_channels: Vec<Channel>,
+    /// Only listen to gamepads whose name contains one of these, ignoring
+    /// case. Everything else, a second player's pad say, is ignored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_gamepads: Option<Vec<String>>,
+    /// Buttons and axes that send on their own channel rather than their
+    /// gamepad's, e.g. drums on 10.
+    #[serde_as(as = "HashMap<ButtonName, ChannelNumber>")]
+    pub button_channels: HashMap<Button, Channel>,
+    #[serde_as(as = "HashMap<AxisName, ChannelNumber>")]
+    pub axis_channels: HashMap<Axis, Channel>,
+    /// Channels to deal NoteOns out to in turn, each note stopped on the
+    /// channel it started on, whatever channel it was mapped to.
+    #[serde_as(as = "Option<Vec<ChannelNumber>>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_rotation: Option<Vec<Channel>>,
+    /// MPE (lower zone): every note gets a channel of its own from
+    /// `mpe_channels`, and pitch bend and channel pressure go to the channel
+    /// of the newest note still sounding, so the stick or trigger bends and
+    /// presses just that note. Polyphonic aftertouch becomes channel
+    /// pressure on its note's channel. With every channel taken, the oldest
+    /// note is stopped to make room.
+    pub mpe: bool,
+    /// The member channels `mpe` picks from. The synth's MPE zone should be
+    /// set to match.
+    #[serde_as(as = "Vec<ChannelNumber>")]
+    pub mpe_channels: Vec<Channel>,
+    #[serde_as(as = "ControlNumber")]
+    pub velocity: U7,
+    /// Buttons that play at a velocity of their own instead of `velocity`,
+    /// e.g. a kick that hits harder than the hats. Used wherever `velocity`
+    /// would be for them, so `velocity_mode` and `velocity_humanize` work
+    /// from it the same way.
+    #[serde_as(as = "HashMap<ButtonName, ControlNumber>")]
+    pub button_velocity: HashMap<Button, U7>,
+    /// Release velocity. 64 is what synths take as "no release velocity".
+    #[serde_as(as = "ControlNumber")]
+    pub note_off_velocity: U7,
+    /// Analog buttons get their release velocity from how fast they were
+    /// let go instead, up to 127 for a full press dropped within 50ms.
+    /// Digital buttons keep `note_off_velocity`.
+    pub release_velocity_from_speed: bool,
+    pub velocity_mode: VelocityMode,
+    /// Nudges each NoteOn's velocity up or down by a random amount up to
+    /// this, so repeated hits don't all sound the same. 0 leaves it alone.
+    pub velocity_humanize: u8,
+    /// Seeds `velocity_humanize` so the same playing comes out with the same
+    /// velocities every run. Unset picks a new seed each run.
+    pub humanize_seed: Option<u64>,
+    /// Semitone offsets from `root` that notes are snapped to, e.g.
+    /// `[0, 2, 4, 5, 7, 9, 11]` for a major scale. Empty plays every note as
+    /// mapped.
+    pub scale: Vec<u8>,
+    #[serde_as(as = "NoteName")]
+    pub root: Note,
+    /// Semitones added to every mapped note.
+    pub transpose: i8,
+    /// The most notes sounding at once on a channel. Past this, the note
+    /// that's been on longest is stopped to make room.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_voices: Option<usize>,
+    /// Sound one button's notes at a time per channel: the last one pressed,
+    /// falling back to the one before when it's let go.
+    pub mono: bool,
+    /// With `mono`, start the new note before stopping the old one, which
+    /// mono synths read as a slur rather than a fresh attack.
+    pub legato: bool,
+    /// Buttons that shift every note in `keys` up or down an octave while
+    /// running. They take precedence over any `keys` entry.
+    #[serde_as(as = "Option<ButtonName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub octave_up_button: Option<Button>,
+    #[serde_as(as = "Option<ButtonName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub octave_down_button: Option<Button>,
+    /// A button that works the sustain pedal (CC 64): down while held, or
+    /// with `sustain_toggle`, down on one press and up on the next. Takes
+    /// precedence over `keys`.
+    #[serde_as(as = "Option<ButtonName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sustain_button: Option<Button>,
+    pub sustain_toggle: bool,
+    /// While held, `shift_layer` replaces `keys`, `chords`,
+    /// `analog_button_ccs` and `axis_ccs`. Takes precedence over everything
+    /// else on the button.
+    #[serde_as(as = "Option<ButtonName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shift_button: Option<Button>,
+    /// Send MIDI clock, 24 ticks per beat, at this tempo.
+    #[serde_as(as = "Option<ShortFloat>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_bpm: Option<f32>,
+    /// Set the clock tempo from the last few presses of this button, starting
+    /// the clock if `clock_bpm` isn't set. Takes precedence over `keys`, as
+    /// do the start and stop buttons.
+    #[serde_as(as = "Option<ButtonName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tap_tempo_button: Option<Button>,
+    #[serde_as(as = "Option<ButtonName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_start_button: Option<Button>,
+    #[serde_as(as = "Option<ButtonName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_stop_button: Option<Button>,
+    /// Buttons that send a transport message when pressed, to drive a
+    /// sequencer. Starting and stopping also runs the clock, if there is
+    /// one. Like the clock buttons they take precedence over `keys`.
+    #[serde_as(as = "HashMap<ButtonName, _>")]
+    pub transport_buttons: HashMap<Button, Transport>,
+    /// Buttons that send Program Changes. Like the octave buttons they take
+    /// precedence over `keys`.
+    #[serde_as(as = "HashMap<ButtonName, _>")]
+    pub program_buttons: HashMap<Button, ProgramButton>,
+    /// Buttons that send Bank Select MSB and LSB and then a Program Change,
+    /// written `[msb, lsb, program]`, for reaching a bank the numbering of
+    /// `program_buttons` doesn't line up with. `Next`/`Prev` go on from
+    /// there.
+    #[serde_as(as = "HashMap<ButtonName, (ControlNumber, ControlNumber, ControlNumber)>")]
+    pub program_bank: HashMap<Button, (U7, U7, U7)>,
+    pub velocity_speed_window_ms: u64,
+    /// Presses and releases coming this soon after the last one let through
+    /// for the same button are taken to be contact bounce and ignored. 0
+    /// turns it off.
+    pub button_debounce_ms: u64,
+    /// Axis positions closer to center than this are treated as centered.
+    #[serde_as(as = "ShortFloat")]
+    pub deadzone: f32,
+    #[serde_as(as = "HashMap<AxisName, ShortFloat>")]
+    pub axis_deadzones: HashMap<Axis, f32>,
+    /// Written by `--calibrate`, and applied to the raw position before
+    /// anything else.
+    #[serde_as(as = "HashMap<AxisName, _>")]
+    pub axis_calibration: HashMap<Axis, Calibration>,
+    /// Axes whose direction is flipped before mapping.
+    #[serde_as(as = "HashSet<AxisName>")]
+    pub invert_axes: HashSet<Axis>,
+    /// A button that flips every axis while running, on top of
+    /// `invert_axes`, and back on the next press. Takes precedence over
+    /// `keys`. With `invert_toggle_resend`, each axis sends again from where
+    /// it is straight away rather than on its next move.
+    #[serde_as(as = "Option<ButtonName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invert_toggle_button: Option<Button>,
+    pub invert_toggle_resend: bool,
+    /// Axes whose CC and pitch bend output eases towards the stick position
+    /// instead of jumping to it. The value is the time constant in
+    /// milliseconds: how long it takes to cover about two thirds of a move.
+    #[serde_as(as = "HashMap<AxisName, ShortFloat>")]
+    pub axis_smoothing: HashMap<Axis, f32>,
+    #[serde_as(as = "HashMap<AxisName, _>")]
+    pub axis_curves: HashMap<Axis, Curve>,
+    /// Axes shaped by a table instead of a curve: the values (0..=127) are
+    /// what the output is at evenly spaced distances from rest to full
+    /// deflection, with straight lines between them. `[0, 127]` is linear.
+    /// Takes precedence over `axis_curves`.
+    #[serde_as(as = "HashMap<AxisName, Vec<ControlNumber>>")]
+    pub axis_curve_table: HashMap<Axis, Vec<U7>>,
+    #[serde_as(as = "HashMap<ButtonName, _>")]
+    pub button_curves: HashMap<Button, Curve>,
+    #[serde_as(as = "HashMap<ButtonName, NoteName>")]
+    pub keys: HashMap<Button, Note>,
+    /// Buttons gilrs reports as `Unknown`, such as back paddles, by the code
+    /// they report (logged at debug level when they're pressed and not
+    /// mapped here). Transposed like `keys`.
+    #[serde_as(as = "HashMap<DisplayFromStr, NoteName>")]
+    pub raw_button_notes: HashMap<u32, Note>,
+    /// For finding out what a button does in a synth's MIDI learn: every
+    /// button nothing else in the config maps sends a CC of its own, 127 on
+    /// press and 0 on release. Its number is this plus the button's code,
+    /// wrapped to stay below 120 where the channel mode messages start.
+    #[serde_as(as = "Option<ControlNumber>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub catchall_button_cc_base: Option<U7>,
+    /// Notes that start, at `velocity`, once every button of a combo is
+    /// held on one gamepad and stop as soon as any of them is let go. The
+    /// buttons still do whatever else they're mapped to. When a press
+    /// completes combos that contain one another only the biggest plays,
+    /// stopping the smaller ones. Transposed like `keys`.
+    pub combo_notes: Vec<ComboNote>,
+    /// Buttons that play one of several notes depending on where another
+    /// control is when they're pressed, e.g. a face button whose octave
+    /// goes by how far the left trigger is pulled. The note picked is the one
+    /// stopped on release, wherever the modifier has moved to by then. They
+    /// take precedence over `chords` and `keys`, and are otherwise played
+    /// like `keys`.
+    #[serde_as(as = "HashMap<ButtonName, _>")]
+    pub modifier_notes: HashMap<Button, ModifierNotes>,
+    /// Buttons that play several notes at once. They take precedence over
+    /// `keys`, and everything that applies to `keys` applies to each note.
+    #[serde_as(as = "HashMap<ButtonName, Vec<NoteName>>")]
+    pub chords: HashMap<Button, Vec<Note>>,
+    /// `keys` buttons that toggle their note on one press and off the next,
+    /// instead of holding it while pressed.
+    #[serde_as(as = "HashSet<ButtonName>")]
+    pub latching_buttons: HashSet<Button>,
+    /// `keys`/`chords` buttons whose notes are played one after another
+    /// while held rather than all at once.
+    #[serde_as(as = "HashSet<ButtonName>")]
+    pub arp_buttons: HashSet<Button>,
+    #[serde_as(as = "ShortFloat")]
+    pub arp_bpm: f32,
+    /// Arpeggio notes per beat, e.g. 4 for sixteenths.
+    pub arp_division: u32,
+    pub arp_pattern: ArpPattern,
+    /// `keys`/`chords` buttons that retrigger their notes every so many
+    /// milliseconds for as long as they're held, like note repeat on a drum
+    /// machine. Not used with `mono`.
+    #[serde_as(as = "HashMap<ButtonName, _>")]
+    pub note_repeat_buttons: HashMap<Button, u64>,
+    /// `keys`/`chords` buttons that retrigger their notes, at their velocity,
+    /// on each repeat gilrs's `Repeat` filter reports while they're held:
+    /// 500ms in, then every 30ms. Not used with `mono`.
+    #[serde_as(as = "HashSet<ButtonName>")]
+    pub repeat_as_retrigger: HashSet<Button>,
+    /// `chords` buttons whose notes start one after another, `strum_ms`
+    /// apart and low to high, like a strummed guitar. Once started they're
+    /// held until release like any chord. Not used with `mono`.
+    #[serde_as(as = "HashSet<ButtonName>")]
+    pub strum_buttons: HashSet<Button>,
+    pub strum_ms: u64,
+    /// Strum high to low instead while this button is held. It does
+    /// nothing else.
+    #[serde_as(as = "Option<ButtonName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strum_down_button: Option<Button>,
+    /// Strum high to low instead while this axis is pushed below center,
+    /// e.g. a stick pulled down.
+    #[serde_as(as = "Option<AxisName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strum_axis: Option<Axis>,
+    /// `keys`/`chords` buttons that stop their notes `gate_ms` after the
+    /// press, however long they're held.
+    #[serde_as(as = "HashSet<ButtonName>")]
+    pub drum_buttons: HashSet<Button>,
+    pub gate_ms: u64,
+    /// Analog buttons sent as a CC of how far they're pressed. One can be
+    /// in `keys`/`chords` or `trigger_note_threshold` as well: the CC keeps
+    /// following it throughout, and the note starts once as it crosses the
+    /// press point (gilrs' own, or the `on` of its threshold).
+    #[serde_as(as = "HashMap<ButtonName, ControlNumber>")]
+    pub analog_button_ccs: HashMap<Button, ControlValue>,
+    /// Buttons that send 127 on a CC while held and 0 on release, e.g. 64
+    /// for a sustain pedal. They take precedence over `keys`.
+    #[serde_as(as = "HashMap<ButtonName, ControlNumber>")]
+    pub button_toggle_ccs: HashMap<Button, ControlValue>,
+    /// Buttons that send a SysEx message when pressed, given as its bytes
+    /// from the 0xF0 to the 0xF7. They take precedence over `keys`.
+    #[serde_as(as = "HashMap<ButtonName, _>")]
+    pub sysex_buttons: HashMap<Button, Vec<u8>>,
+    /// Buttons that send these bytes as they are when pressed, for whatever
+    /// a device wants that nothing else here sends. They aren't checked
+    /// beyond a warning at load if they don't read as MIDI, and go to
+    /// `output_port_name` whatever `outputs` says. They take precedence
+    /// over `keys`.
+    #[serde_as(as = "HashMap<ButtonName, _>")]
+    pub raw_button_bytes: HashMap<Button, Vec<u8>>,
+    /// Buttons that send a sequence of messages when pressed, taking
+    /// precedence over `keys`. Pressing one again before it's done starts
+    /// it over rather than running two at once.
+    #[serde_as(as = "HashMap<ButtonName, _>")]
+    pub macros: HashMap<Button, Vec<MacroStep>>,
+    /// Steps like a macro's, sent once at startup right after the output
+    /// port is opened to put the synth in a known state: program, CCs,
+    /// pitch bend and so on.
+    pub on_connect: Vec<MacroStep>,
+    /// Analog buttons sent as channel pressure instead of (or as well as) a CC.
+    #[serde_as(as = "HashSet<ButtonName>")]
+    pub analog_button_aftertouch: HashSet<Button>,
+    /// Analog buttons sent as polyphonic aftertouch on the notes held by the
+    /// listed buttons of the same gamepad, or on every note it's holding if
+    /// the list is empty. Pressure with nothing held goes nowhere.
+    #[serde_as(as = "HashMap<ButtonName, HashSet<ButtonName>>")]
+    pub poly_aftertouch_buttons: HashMap<Button, HashSet<Button>>,
+    #[serde_as(as = "HashMap<AxisName, ControlNumber>")]
+    pub axis_ccs: HashMap<Axis, ControlValue>,
+    /// Axes gilrs doesn't have a name for, such as the gyro and
+    /// accelerometer of some controllers, by the code they report (logged
+    /// at debug level for the ones not mapped here). Each gets a centered CC
+    /// like `axis_ccs`, after the global `deadzone`.
+    #[serde_as(as = "HashMap<DisplayFromStr, ControlNumber>")]
+    pub raw_axis_ccs: HashMap<u32, ControlValue>,
+    /// Axes from `axis_ccs` sent at 14-bit resolution, as the MSB on their CC
+    /// (which must be below 32) and the LSB on CC + 32.
+    #[serde_as(as = "HashSet<AxisName>")]
+    pub hires_axis_ccs: HashSet<Axis>,
+    /// `[min, max]` for the CC of `axis_ccs` axes that shouldn't sweep the
+    /// whole 0..=127: the output is squeezed in between, so inverted axes
+    /// still run from max to min.
+    #[serde_as(as = "HashMap<AxisName, (ControlNumber, ControlNumber)>")]
+    pub axis_range: HashMap<Axis, (U7, U7)>,
+    /// The CC value `axis_ccs` axes rest at, 64 if unset. Deflection moves
+    /// the same distance either way from it as it would from 64, clamped to
+    /// 0..=127, so a center of 0 only rises on the positive side.
+    #[serde_as(as = "HashMap<AxisName, ControlNumber>")]
+    pub axis_center: HashMap<Axis, U7>,
+    /// Axes that crossfade between a pair of CCs, `[a, b]`: going from -1
+    /// to +1, `a` falls from 127 to 0 while `b` rises from 0 to 127, and
+    /// both are at 64 in the middle.
+    #[serde_as(as = "HashMap<AxisName, (ControlNumber, ControlNumber)>")]
+    pub axis_crossfade: HashMap<Axis, (ControlValue, ControlValue)>,
+    /// Axes and analog buttons sent to an NRPN (0..=16383) with 14-bit data
+    /// entry, for parameters a synth doesn't put on a CC.
+    #[serde_as(as = "HashMap<AxisName, _>")]
+    pub axis_nrpn: HashMap<Axis, u16>,
+    #[serde_as(as = "HashMap<ButtonName, _>")]
+    pub analog_button_nrpn: HashMap<Button, u16>,
+    /// The most messages per second to send on any one CC. Changes coming in
+    /// faster are held back and only the latest goes out once it's allowed,
+    /// and like any CC it's dropped then if it's back to the value last sent.
+    /// Bank Select, (N)RPN and channel mode messages are never held back.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cc_rate_limit: Option<u32>,
+    /// Experimental: an axis that scrubs a sequencer by sending Song
+    /// Position Pointers, its travel from -1 to 1 spanning the 16384
+    /// sixteenths SPP can reach. A trigger suits it better than a stick,
+    /// which would jump to the middle of that whenever it's let go.
+    #[serde_as(as = "Option<AxisName>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub song_position_axis: Option<Axis>,
+    /// The most Song Position Pointers to send a second, held back like
+    /// `cc_rate_limit` so scrubbing doesn't flood the sequencer.
+    pub song_position_rate: u32,
+    /// Axes sent as 14-bit pitch bend, centered on 8192.
+    #[serde_as(as = "HashSet<AxisName>")]
+    pub axis_pitchbend: HashSet<Axis>,
+    /// How much of the full pitch bend range full deflection reaches.
+    #[serde_as(as = "ShortFloat")]
+    pub pitchbend_range: f32,
+    /// Axes that play a note picked from a (low, high) range by their
+    /// position, retriggering as the note changes and silent at rest.
+    #[serde_as(as = "HashMap<AxisName, (NoteName, NoteName)>")]
+    pub axis_notes: HashMap<Axis, (Note, Note)>,
+    /// Analog buttons that play a note once pressed past a threshold,
+    /// `[note, on, off]`: it starts when the pressure rises to `on` and
+    /// stops once it falls back to `off`, which should be a bit lower so it
+    /// doesn't chatter around one point. Transposed like `keys`.
+    #[serde_as(as = "HashMap<ButtonName, (NoteName, ShortFloat, ShortFloat)>")]
+    pub trigger_note_threshold: HashMap<Button, (Note, f32, f32)>,
+    /// Sticks read as a whole, sending notes and CCs by which of the eight
+    /// directions they're pushed in rather than a value per axis.
+    #[serde_as(as = "HashMap<DisplayFromStr, _>")]
+    pub stick_zones: HashMap<Stick, StickZones>,
+    pub shift_layer: Layer,
+    /// Alternatives to the top level mappings, cycled through (after the
+    /// top level itself, named "default") by pressing all of `profile_combo`.
+    pub profiles: Vec<Profile>,
+    #[serde_as(as = "Vec<ButtonName>")]
+    pub profile_combo: Vec<Button>,
+    /// The profile a gamepad starts on, by its name: each key is matched
+    /// against the name the way `allowed_gamepads` is, the longest match
+    /// winning, and each value names a profile ("default" for the top
+    /// level). Gamepads matching none share the one `profile_combo` picks.
+    /// Those that match switch on their own with `profile_combo`.
+    pub gamepad_profiles: HashMap<String, String>,
+    /// Buttons that, pressed together (or just the one), stop everything:
+    /// every note, and on each channel used so far the sustain pedal and
+    /// pitch bend are reset and All Sound Off and All Notes Off sent.
+    #[serde_as(as = "Vec<ButtonName>")]
+    pub panic_combo: Vec<Button>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rumble: Option<Rumble>,
+    pub osc: Osc,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        let mut cfg = Config {
+            output_port_name: "xbox".to_string(),
+            create_virtual: false,
+            outputs: Vec::new(),
+            thru_port: None,
+            poll_interval_ms: 1,
+            max_events_per_tick: None,
+            gilrs_init_attempts: 5,
+            gilrs_init_retry_ms: 500,
+            output_midi_channel: Channel::Ch15,
+            gamepad_channels: Vec::new(),
+            allowed_gamepads: None,
+            button_channels: HashMap::new(),
+            axis_channels: HashMap::new(),
+            channel_rotation: None,
+            mpe: false,
+            mpe_channels: (1..16).map(|i| Channel::from_index(i).unwrap()).collect(),
+            velocity: U7::from_u8_lossy(80),
+            button_velocity: HashMap::new(),
+            note_off_velocity: U7::from_u8_lossy(64),
+            release_velocity_from_speed: false,
+            max_voices: None,
+            mono: false,
+            legato: false,
+            velocity_mode: VelocityMode::Fixed,
+            velocity_humanize: 0,
+            humanize_seed: None,
+            scale: Vec::new(),
+            root: Note::C4,
+            transpose: 0,
+            octave_up_button: None,
+            octave_down_button: None,
+            sustain_button: None,
+            sustain_toggle: false,
+            shift_button: None,
+            clock_bpm: None,
+            tap_tempo_button: None,
+            clock_start_button: None,
+            clock_stop_button: None,
+            transport_buttons: HashMap::new(),
+            program_buttons: HashMap::new(),
+            program_bank: HashMap::new(),
+            velocity_speed_window_ms: 500,
+            button_debounce_ms: 0,
+            deadzone: 0.08,
+            axis_deadzones: HashMap::new(),
+            axis_calibration: HashMap::new(),
+            invert_axes: HashSet::new(),
+            invert_toggle_button: None,
+            invert_toggle_resend: true,
+            axis_smoothing: HashMap::new(),
+            axis_curves: HashMap::new(),
+            axis_curve_table: HashMap::new(),
+            button_curves: HashMap::new(),
+            keys: HashMap::new(),
+            raw_button_notes: HashMap::new(),
+            catchall_button_cc_base: None,
+            combo_notes: Vec::new(),
+            modifier_notes: HashMap::new(),
+            chords: HashMap::new(),
+            latching_buttons: HashSet::new(),
+            arp_buttons: HashSet::new(),
+            arp_bpm: 120.0,
+            arp_division: 4,
+            note_repeat_buttons: HashMap::new(),
+            repeat_as_retrigger: HashSet::new(),
+            strum_buttons: HashSet::new(),
+            strum_ms: 15,
+            strum_down_button: None,
+            strum_axis: None,
+            arp_pattern: ArpPattern::Up,
+            drum_buttons: HashSet::new(),
+            gate_ms: 50,
+            analog_button_ccs: HashMap::new(),
+            button_toggle_ccs: HashMap::new(),
+            sysex_buttons: HashMap::new(),
+            raw_button_bytes: HashMap::new(),
+            macros: HashMap::new(),
+            on_connect: Vec::new(),
+            analog_button_aftertouch: HashSet::new(),
+            poly_aftertouch_buttons: HashMap::new(),
+            axis_ccs: HashMap::new(),
+            raw_axis_ccs: HashMap::new(),
+            hires_axis_ccs: HashSet::new(),
+            axis_range: HashMap::new(),
+            axis_center: HashMap::new(),
+            axis_crossfade: HashMap::new(),
+            axis_nrpn: HashMap::new(),
+            analog_button_nrpn: HashMap::new(),
+            cc_rate_limit: None,
+            song_position_axis: None,
+            song_position_rate: 20,
+            axis_pitchbend: HashSet::new(),
+            pitchbend_range: 1.0,
+            axis_notes: HashMap::new(),
+            trigger_note_threshold: HashMap::new(),
+            stick_zones: HashMap::new(),
+            shift_layer: Layer::default(),
+            profiles: Vec::new(),
+            profile_combo: Vec::new(),
+            gamepad_profiles: HashMap::new(),
+            panic_combo: Vec::new(),
+            rumble: None,
+            osc: Osc::default(),
+        };
+        cfg.keys.extend(vec![
+            (Button::North, Note::C1),
+            (Button::East, Note::D1),
+            (Button::South, Note::E1),
+            (Button::West, Note::F1),
+            (Button::LeftTrigger, Note::A2),
+            (Button::RightTrigger, Note::B2),
+            (Button::Start, Note::C3),
+            (Button::Select, Note::D3),
+            (Button::Mode, Note::E3),
+            (Button::DPadUp, Note::A4),
+            (Button::DPadDown, Note::B4),
+            (Button::DPadLeft, Note::C4),
+            (Button::DPadRight, Note::D4),
+        ]);
+        cfg.analog_button_ccs.extend(vec![
+            (Button::LeftTrigger2, U7::from_u8_lossy(1)),
+            (Button::RightTrigger2, U7::from_u8_lossy(2)),
+        ]);
+        cfg.axis_ccs.extend(vec![
+            (Axis::LeftStickX, U7::from_u8_lossy(3)),
+            (Axis::LeftStickY, U7::from_u8_lossy(4)),
+            (Axis::RightStickX, U7::from_u8_lossy(5)),
+            (Axis::RightStickY, U7::from_u8_lossy(6)),
+        ]);
+        cfg
+    }
+}
+
+impl Config {
+    /// Snaps a MIDI note number to the nearest note in `scale`, if one is set.
+    pub(crate) fn quantize(&self, note: u8) -> Note {
+        if self.scale.is_empty() {
+            return Note::from_u8_lossy(note);
+        }
+        quantize_to_scale(note, self.root, &self.scale)
+    }
+
+    /// The shift layer while `shifted`, otherwise profile number `profile`,
+    /// where 0 is the top level of the config.
+    pub(crate) fn mapping(&self, profile: usize, shifted: bool) -> Mapping<'_> {
+        if shifted {
+            return Mapping::from(&self.shift_layer);
+        }
+        match profile.checked_sub(1).and_then(|i| self.profiles.get(i)) {
+            Some(p) => Mapping::from(&p.layer),
+            None => Mapping {
+                keys: &self.keys,
+                chords: &self.chords,
+                analog_button_ccs: &self.analog_button_ccs,
+                axis_ccs: &self.axis_ccs,
+            },
+        }
+    }
+
+    /// The profile from `gamepad_profiles` for a gamepad called `name`.
+    pub(crate) fn gamepad_profile(&self, name: &str) -> Option<usize> {
+        let name = name.to_lowercase();
+        let (_, profile) = self
+            .gamepad_profiles
+            .iter()
+            .filter(|(wanted, _)| name.contains(&wanted.to_lowercase()))
+            .max_by_key(|(wanted, _)| (wanted.len(), *wanted))?;
+        self.profile_index(profile)
+    }
+
+    fn profile_index(&self, name: &str) -> Option<usize> {
+        if name == "default" {
+            return Some(0);
+        }
+        self.profiles
+            .iter()
+            .position(|p| p.name == name)
+            .map(|i| i + 1)
+    }
+
+    pub(crate) fn profile_name(&self, profile: usize) -> &str {
+        match profile.checked_sub(1).and_then(|i| self.profiles.get(i)) {
+            Some(p) => &p.name,
+            None => "default",
+        }
+    }
+
+    /// The velocity `btn` plays at before `velocity_mode`.
+    pub(crate) fn button_velocity(&self, btn: Button) -> U7 {
+        self.button_velocity
+            .get(&btn)
+            .copied()
+            .unwrap_or(self.velocity)
+    }
+
+    /// Whether anything in the config, in any layer or profile, is mapped
+    /// to `btn`, or to `code` for an `Unknown` one.
+    fn maps_button(&self, btn: Button, code: u32) -> bool {
+        if btn == Button::Unknown {
+            return self.raw_button_notes.contains_key(&code);
+        }
+        let buttons = [
+            self.octave_up_button,
+            self.octave_down_button,
+            self.sustain_button,
+            self.shift_button,
+            self.tap_tempo_button,
+            self.clock_start_button,
+            self.clock_stop_button,
+            self.strum_down_button,
+            self.invert_toggle_button,
+        ];
+        let layers =
+            || std::iter::once(&self.shift_layer).chain(self.profiles.iter().map(|p| &p.layer));
+        buttons.contains(&Some(btn))
+            || self.keys.contains_key(&btn)
+            || self.chords.contains_key(&btn)
+            || self.analog_button_ccs.contains_key(&btn)
+            || layers().any(|l| {
+                l.keys.contains_key(&btn)
+                    || l.chords.contains_key(&btn)
+                    || l.analog_button_ccs.contains_key(&btn)
+            })
+            || self.modifier_notes.contains_key(&btn)
+            || self.modifier_notes.values().any(|m| m.button == Some(btn))
+            || self.combo_notes.iter().any(|c| c.buttons.contains(&btn))
+            || self.panic_combo.contains(&btn)
+            || self.profile_combo.contains(&btn)
+            || self.transport_buttons.contains_key(&btn)
+            || self.program_buttons.contains_key(&btn)
+            || self.program_bank.contains_key(&btn)
+            || self.button_toggle_ccs.contains_key(&btn)
+            || self.sysex_buttons.contains_key(&btn)
+            || self.raw_button_bytes.contains_key(&btn)
+            || self.macros.contains_key(&btn)
+            || self.analog_button_aftertouch.contains(&btn)
+            || self.poly_aftertouch_buttons.contains_key(&btn)
+            || self.analog_button_nrpn.contains_key(&btn)
+            || self.trigger_note_threshold.contains_key(&btn)
+    }
+
+    /// The `catchall_button_cc_base` CC for `btn`, if it has one.
+    pub(crate) fn catchall_cc(&self, btn: Button, code: u32) -> Option<ControlFunction> {
+        let base = u8::from(self.catchall_button_cc_base?) as u32;
+        if self.maps_button(btn, code) {
+            return None;
+        }
+        Some(ControlFunction(U7::from_u8_lossy(
+            (base + code % (120 - base)) as u8,
+        )))
+    }
+
+    /// The channel `btn` sends on, given the one its gamepad sends on.
+    pub(crate) fn button_channel(&self, btn: Button, pad: Channel) -> Channel {
+        self.button_channels.get(&btn).copied().unwrap_or(pad)
+    }
+
+    pub(crate) fn axis_channel(&self, axis: Axis, pad: Channel) -> Channel {
+        self.axis_channels.get(&axis).copied().unwrap_or(pad)
+    }
+
+    /// A button's analog position after its curve.
+    pub fn shape_button(&self, btn: Button, pos: f32) -> f32 {
+        match self.button_curves.get(&btn) {
+            Some(curve) => curve.apply(pos),
+            None => pos,
+        }
+    }
+
+    /// A raw axis position after its calibration and inversion, which is
+    /// what stick zones go by.
+    pub fn calibrate_axis(&self, axis: Axis, pos: f32) -> f32 {
+        let pos = match self.axis_calibration.get(&axis) {
+            Some(calibration) => calibration.apply(pos),
+            None => pos,
+        };
+        if self.invert_axes.contains(&axis) {
+            -pos
+        } else {
+            pos
+        }
+    }
+
+    /// A calibrated axis position after its deadzone and curve, ready to be
+    /// scaled to MIDI. Inside the deadzone it's exactly 0.0, so pitch bend
+    /// and every other centered output lands on its exact center however
+    /// far off the stick comes to rest.
+    pub fn shape_axis(&self, axis: Axis, pos: f32) -> f32 {
+        let pos = apply_deadzone(pos, self.deadzone(axis));
+        if pos == 0.0 {
+            // Not through the curve: `Gamma(0)` would lift it to 1, and the
+            // sign of a -0.0 would go along with it.
+            return 0.0;
+        }
+        if let Some(table) = self.axis_curve_table.get(&axis) {
+            return pos.signum() * lookup(table, pos.abs());
+        }
+        match self.axis_curves.get(&axis) {
+            Some(curve) => curve.apply_centered(pos),
+            None => pos,
+        }
+    }
+
+    fn deadzone(&self, axis: Axis) -> f32 {
+        self.axis_deadzones
+            .get(&axis)
+            .copied()
+            .unwrap_or(self.deadzone)
+    }
+
+    /// The default config with nothing mapped: no keys and no CCs, so only
+    /// what a config file adds does anything.
+    pub fn unmapped() -> Config {
+        Config {
+            keys: HashMap::new(),
+            analog_button_ccs: HashMap::new(),
+            axis_ccs: HashMap::new(),
+            ..Config::default()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Config> {
+        Config::read(path, true)
+    }
+
+    /// Like `load`, but the mapping the file leaves out stays empty instead of
+    /// falling back to the built-in one, as in `unmapped`.
+    pub fn load_unmapped(path: &Path) -> Result<Config> {
+        Config::read(path, false)
+    }
+
+    fn read(path: &Path, default_mapping: bool) -> Result<Config> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("reading config {}", path.display()))?;
+        let mut cfg: Config =
+            toml::from_str(&text).wrap_err_with(|| format!("parsing config {}", path.display()))?;
+        if !default_mapping {
+            // It parsed as a Config, so it parses as a table.
+            let given: toml::value::Table = toml::from_str(&text)?;
+            if !given.contains_key("keys") {
+                cfg.keys.clear();
+            }
+            if !given.contains_key("analog_button_ccs") {
+                cfg.analog_button_ccs.clear();
+            }
+            if !given.contains_key("axis_ccs") {
+                cfg.axis_ccs.clear();
+            }
+        }
+        cfg.validate()
+            .wrap_err_with(|| format!("checking config {}", path.display()))?;
+        Ok(cfg)
+    }
+
+    /// Writes the config back out in the form `load` reads.
+    pub fn to_toml(&self) -> Result<String> {
+        // Going through a Value sorts the keys and puts plain values ahead of
+        // tables, which serializing the struct directly would need by hand.
+        Ok(toml::to_string(&toml::Value::try_from(self)?)?)
+    }
+
+    /// Checks what the types alone can't.
+    fn validate(&self) -> Result<()> {
+        if let Some(base) = self.catchall_button_cc_base {
+            if u8::from(base) >= 120 {
+                return Err(eyre!(
+                    "catchall_button_cc_base is {}, it has to be below 120 where the channel mode messages start",
+                    u8::from(base)
+                ));
+            }
+        }
+        if self.output_port_name.is_empty() {
+            return Err(eyre!(
+                "output_port_name is empty, which would match any port; name the port to send to"
+            ));
+        }
+        let shift = self.transpose as i16;
+        let layers =
+            || std::iter::once(&self.shift_layer).chain(self.profiles.iter().map(|p| &p.layer));
+        let notes = self
+            .keys
+            .values()
+            .chain(self.chords.values().flatten())
+            .chain(layers().flat_map(|l| l.keys.values().chain(l.chords.values().flatten())))
+            .chain(self.axis_notes.values().flat_map(|(lo, hi)| vec![lo, hi]))
+            .chain(
+                self.trigger_note_threshold
+                    .values()
+                    .map(|(note, _, _)| note),
+            );
+        for note in notes {
+            let n = u8::from(*note) as i16 + shift;
+            if !(0..=127).contains(&n) {
+                log::warn!(
+                    "transpose {:+} moves {} off the keyboard, it will be clamped",
+                    shift,
+                    names::note_name(*note)
+                );
+            }
+        }
+        for axis in self.hires_axis_ccs.iter() {
+            match self.axis_ccs.get(axis) {
+                Some(cc) if u8::from(*cc) < 32 => {}
+                Some(cc) => {
+                    return Err(eyre!(
+                        "hires_axis_ccs: {} is on CC {}, 14-bit CCs must be below 32",
+                        names::axis_name(*axis),
+                        u8::from(*cc)
+                    ))
+                }
+                None => {
+                    return Err(eyre!(
+                        "hires_axis_ccs: {} has no entry in axis_ccs",
+                        names::axis_name(*axis)
+                    ))
+                }
+            }
+            if let Some(cc) =
+                layers().find_map(|l| l.axis_ccs.get(axis).filter(|cc| u8::from(**cc) >= 32))
+            {
+                return Err(eyre!(
+                    "hires_axis_ccs: {} is on CC {} in a layer or profile, 14-bit CCs must be below 32",
+                    names::axis_name(*axis),
+                    u8::from(*cc)
+                ));
+            }
+        }
+        for (btn, action) in self.program_buttons.iter() {
+            if let ProgramButton::Program(n) = action {
+                if *n > MAX_PROGRAM {
+                    return Err(eyre!(
+                        "program_buttons: {} is set to program {}, the highest is {}",
+                        names::button_name(*btn),
+                        n,
+                        MAX_PROGRAM
+                    ));
+                }
+            }
+        }
+        if let Some(btn) = self
+            .program_bank
+            .keys()
+            .find(|btn| self.program_buttons.contains_key(btn))
+        {
+            return Err(eyre!(
+                "program_bank: {} is in program_buttons too",
+                names::button_name(*btn)
+            ));
+        }
+        if self.arp_bpm.is_nan() || self.arp_bpm <= 0.0 || self.arp_division == 0 {
+            return Err(eyre!(
+                "arp_bpm and arp_division must be above 0, not {} and {}",
+                self.arp_bpm,
+                self.arp_division
+            ));
+        }
+        for (axis, ms) in self.axis_smoothing.iter() {
+            if ms.is_nan() || *ms < 0.0 {
+                return Err(eyre!(
+                    "axis_smoothing: {} is {}, it can't be negative",
+                    names::axis_name(*axis),
+                    ms
+                ));
+            }
+        }
+        for (axis, c) in self.axis_calibration.iter() {
+            if !(c.min < c.center && c.center < c.max) {
+                return Err(eyre!(
+                    "axis_calibration: {} needs min < center < max, not {} < {} < {}",
+                    names::axis_name(*axis),
+                    c.min,
+                    c.center,
+                    c.max
+                ));
+            }
+        }
+        for (axis, (min, max)) in self.axis_range.iter() {
+            if min >= max {
+                return Err(eyre!(
+                    "axis_range: {} is {}..{}, min has to be below max",
+                    names::axis_name(*axis),
+                    u8::from(*min),
+                    u8::from(*max)
+                ));
+            }
+        }
+        for (btn, (_, on, off)) in self.trigger_note_threshold.iter() {
+            if !(0.0 <= *off && off < on && *on <= 1.0) {
+                return Err(eyre!(
+                    "trigger_note_threshold: {} needs 0 <= off < on <= 1, not on {} and off {}",
+                    names::button_name(*btn),
+                    on,
+                    off
+                ));
+            }
+        }
+        // Anything from 1 up leaves the axis dead, and past that the rescale
+        // after it divides by zero or flips sign.
+        if !(0.0..1.0).contains(&self.deadzone) {
+            return Err(eyre!(
+                "deadzone is {}, it has to be at least 0 and below 1",
+                self.deadzone
+            ));
+        }
+        for (axis, deadzone) in self.axis_deadzones.iter() {
+            if !(0.0..1.0).contains(deadzone) {
+                return Err(eyre!(
+                    "axis_deadzones: {}'s deadzone is {}, it has to be at least 0 and below 1",
+                    names::axis_name(*axis),
+                    deadzone
+                ));
+            }
+        }
+        for (stick, zones) in self.stick_zones.iter() {
+            if zones.deadzone.is_nan() || zones.deadzone >= 1.0 {
+                return Err(eyre!(
+                    "stick_zones: the {:?} stick's deadzone is {}, it has to be below 1",
+                    stick,
+                    zones.deadzone
+                ));
+            }
+        }
+        for (btn, bytes) in self.sysex_buttons.iter() {
+            if !is_sysex(bytes) {
+                return Err(eyre!(
+                    "sysex_buttons: {} must start with 0xF0, end with 0xF7 and have only 7-bit bytes between",
+                    names::button_name(*btn)
+                ));
+            }
+        }
+        for (btn, bytes) in self.raw_button_bytes.iter() {
+            if !is_midi(bytes) {
+                log::warn!(
+                    "raw_button_bytes: {} sends {:02X?}, which isn't a whole MIDI message",
+                    names::button_name(*btn),
+                    bytes
+                );
+            }
+        }
+        for (btn, ms) in self.note_repeat_buttons.iter() {
+            if *ms == 0 {
+                return Err(eyre!(
+                    "note_repeat_buttons: {} repeats every 0ms, it needs to be at least 1",
+                    names::button_name(*btn)
+                ));
+            }
+        }
+        for (axis, table) in self.axis_curve_table.iter() {
+            if table.is_empty() {
+                return Err(eyre!(
+                    "axis_curve_table: {} has an empty table",
+                    names::axis_name(*axis)
+                ));
+            }
+        }
+        for (i, combo) in self.combo_notes.iter().enumerate() {
+            if combo.buttons.len() < 2 {
+                return Err(eyre!(
+                    "combo_notes: entry {} needs at least two buttons, use keys for one",
+                    i + 1
+                ));
+            }
+        }
+        for (btn, m) in self.modifier_notes.iter() {
+            let btn = names::button_name(*btn);
+            if m.notes.is_empty() {
+                return Err(eyre!("modifier_notes: {} has no notes", btn));
+            }
+            if m.button.is_some() == m.axis.is_some() {
+                return Err(eyre!(
+                    "modifier_notes: {} needs exactly one of button and axis",
+                    btn
+                ));
+            }
+        }
+        for (btn, steps) in self.macros.iter() {
+            check_steps("macros", Some(names::button_name(*btn)), steps)?;
+        }
+        check_steps("on_connect", None, &self.on_connect)?;
+        let mut nrpns = self
+            .axis_nrpn
+            .values()
+            .chain(self.analog_button_nrpn.values());
+        if let Some(n) = nrpns.find(|n| **n > 16383) {
+            return Err(eyre!("NRPN {} is out of range, the highest is 16383", n));
+        }
+        if self.cc_rate_limit == Some(0) {
+            return Err(eyre!("cc_rate_limit must be at least 1"));
+        }
+        if self.rumble.as_ref().is_some_and(|r| r.port.is_empty()) {
+            return Err(eyre!("rumble needs the port to listen on"));
+        }
+        if self.gilrs_init_attempts == 0 {
+            return Err(eyre!("gilrs_init_attempts must be at least 1"));
+        }
+        if self.velocity == U7::MIN {
+            return Err(eyre!(
+                "velocity must be at least 1, a NoteOn at 0 is a NoteOff"
+            ));
+        }
+        for (btn, velocity) in self.button_velocity.iter() {
+            if *velocity == U7::MIN {
+                return Err(eyre!(
+                    "button_velocity: {} must be at least 1, a NoteOn at 0 is a NoteOff",
+                    names::button_name(*btn)
+                ));
+            }
+        }
+        if self.channel_rotation.as_ref().is_some_and(|c| c.is_empty()) {
+            return Err(eyre!("channel_rotation needs at least one channel"));
+        }
+        if self.mpe && self.mpe_channels.is_empty() {
+            return Err(eyre!("mpe needs at least one channel in mpe_channels"));
+        }
+        if self.mpe && self.channel_rotation.is_some() {
+            return Err(eyre!(
+                "mpe and channel_rotation both pick the channel of each note, use one or the other"
+            ));
+        }
+        if self.max_events_per_tick == Some(0) {
+            return Err(eyre!("max_events_per_tick must be at least 1"));
+        }
+        if self.max_voices == Some(0) {
+            return Err(eyre!("max_voices must be at least 1"));
+        }
+        if let Some(bpm) = self.clock_bpm {
+            if bpm.is_nan() || bpm <= 0.0 {
+                return Err(eyre!("clock_bpm must be above 0, not {}", bpm));
+            }
+        }
+        for (gamepad, profile) in self.gamepad_profiles.iter() {
+            if self.profile_index(profile).is_none() {
+                return Err(eyre!(
+                    "gamepad_profiles: {:?} is set to profile {:?}, which isn't in profiles",
+                    gamepad,
+                    profile
+                ));
+            }
+        }
+        // `gamepad_profiles` can pick them without a combo.
+        if !self.profiles.is_empty()
+            && self.profile_combo.is_empty()
+            && self.gamepad_profiles.is_empty()
+        {
+            return Err(eyre!(
+                "profiles are set but there's no way to switch to them; set profile_combo or gamepad_profiles"
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+//! The `--json` output: one object per line for each gamepad event and each
+//! MIDI message sent, for other programs to read. The same lines go to
+//! `--ws` clients.
+
+use gamepad2midi::names;
+use gilrs::{EventType, GamepadId};
+use serde::Serialize;
+use std::io::Write;
+use std::sync::mpsc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Line<'a> {
+    Event {
+        /// Seconds since the Unix epoch.
+        time: f64,
+        gamepad: usize,
+        event: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        button: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        axis: Option<&'static str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        value: Option<f32>,
+    },
+    Midi {
+        time: f64,
+        /// What produced it, as in the dry run printout.
+        cause: String,
+        bytes: &'a [u8],
+        message: String,
+    },
+}
+
+fn seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Where the lines go, if anywhere.
+#[derive(Default)]
+pub struct Sink {
+    pub stdout: bool,
+    pub ws: Option<mpsc::Sender<String>>,
+}
+
+impl Sink {
+    fn wanted(&self) -> bool {
+        self.stdout || self.ws.is_some()
+    }
+
+    fn write(&mut self, line: &Line) {
+        let text = match serde_json::to_string(line) {
+            Ok(text) => text,
+            Err(e) => return log::error!("Writing JSON: {}", e),
+        };
+        if self.stdout {
+            // Flushed every line, so whatever's reading sees it straight away.
+            let stdout = std::io::stdout();
+            let mut stdout = stdout.lock();
+            if let Err(e) = writeln!(stdout, "{}", text).and_then(|()| stdout.flush()) {
+                log::error!("Writing JSON: {}", e);
+            }
+        }
+        if let Some(ws) = &self.ws {
+            // The server thread only goes if it can't listen any more.
+            if ws.send(text).is_err() {
+                self.ws = None;
+            }
+        }
+    }
+
+    pub fn event(&mut self, id: GamepadId, event: &EventType, time: SystemTime) {
+        if self.wanted() {
+            self.write(&event_line(id, event, time));
+        }
+    }
+
+    pub fn midi(&mut self, cause: impl std::fmt::Display, bytes: &[u8], message: String) {
+        if self.wanted() {
+            self.write(&Line::Midi {
+                time: seconds(SystemTime::now()),
+                cause: cause.to_string(),
+                bytes,
+                message,
+            });
+        }
+    }
+}
+
+fn event_line(id: GamepadId, event: &EventType, time: SystemTime) -> Line<'static> {
+    let (name, button, axis, value) = match *event {
+        EventType::ButtonPressed(btn, _) => ("ButtonPressed", Some(btn), None, None),
+        EventType::ButtonRepeated(btn, _) => ("ButtonRepeated", Some(btn), None, None),
+        EventType::ButtonReleased(btn, _) => ("ButtonReleased", Some(btn), None, None),
+        EventType::ButtonChanged(btn, v, _) => ("ButtonChanged", Some(btn), None, Some(v)),
+        EventType::AxisChanged(ax, v, _) => ("AxisChanged", None, Some(ax), Some(v)),
+        EventType::Connected => ("Connected", None, None, None),
+        EventType::Disconnected => ("Disconnected", None, None, None),
+        EventType::Dropped => ("Dropped", None, None, None),
+    };
+    Line::Event {
+        time: seconds(time),
+        gamepad: id.into(),
+        event: name,
+        button: button.map(names::button_name),
+        axis: axis.map(names::axis_name),
+        value,
+    }
+}
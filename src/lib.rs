@@ -0,0 +1,256 @@
+//! The mapping from gamepad events to MIDI and the config that drives it.
+//! The binary wires this up to gilrs and midir; `map_event` and
+//! `State::tick` are the way in for anything else that wants to.
+//!
+//! `Config` and its checks live in `config`, `State` and `map_event` in
+//! `state`; both are re-exported here. What's left is the conversions
+//! between gamepad positions and MIDI values that they share.
+
+use std::convert::TryFrom;
+use wmidi::{Channel, ControlFunction, MidiMessage, Note, U14, U7};
+
+mod config;
+pub mod names;
+pub mod osc;
+pub mod smf;
+mod state;
+
+pub use config::*;
+pub use state::*;
+
+/// Where a centered CC rests unless `axis_center` says otherwise.
+const CC_CENTER: U7 = U7::from_u8_lossy(64);
+
+/// Whether `bytes` is a whole SysEx message: F0, 7-bit data, F7.
+fn is_sysex(bytes: &[u8]) -> bool {
+    let framed = bytes.len() >= 2 && bytes[0] == 0xF0 && bytes[bytes.len() - 1] == 0xF7;
+    framed && bytes[1..bytes.len() - 1].iter().all(|b| *b <= 0x7F)
+}
+
+/// Whether `bytes` are one or more whole MIDI messages, back to back.
+pub fn is_midi(mut bytes: &[u8]) -> bool {
+    if bytes.is_empty() {
+        return false;
+    }
+    while !bytes.is_empty() {
+        match MidiMessage::try_from(bytes) {
+            Ok(mm) => bytes = &bytes[mm.bytes_size()..],
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+/// The message for SysEx `bytes` that have passed `is_sysex`.
+fn sysex_message(bytes: &[u8]) -> MidiMessage<'static> {
+    // The message wants just the data between the F0 and F7.
+    let data = &bytes[1..bytes.len() - 1];
+    MidiMessage::OwnedSysEx(data.iter().map(|b| U7::from_u8_lossy(*b)).collect())
+}
+
+/// A one-line, human readable form of `mm`, e.g. `ch15 NoteOn E1 velocity 80`.
+pub fn describe_message(mm: &MidiMessage) -> String {
+    match mm {
+        MidiMessage::NoteOn(ch, note, velocity) => format!(
+            "ch{} NoteOn {} velocity {}",
+            ch.number(),
+            names::note_name(*note),
+            u8::from(*velocity)
+        ),
+        MidiMessage::NoteOff(ch, note, velocity) => format!(
+            "ch{} NoteOff {} velocity {}",
+            ch.number(),
+            names::note_name(*note),
+            u8::from(*velocity)
+        ),
+        MidiMessage::ControlChange(ch, cc, value) => format!(
+            "ch{} CC {} = {}",
+            ch.number(),
+            u8::from(cc.0),
+            u8::from(*value)
+        ),
+        MidiMessage::ChannelPressure(ch, value) => {
+            format!("ch{} ChannelPressure {}", ch.number(), u8::from(*value))
+        }
+        MidiMessage::PitchBendChange(ch, value) => format!(
+            "ch{} PitchBend {:+}",
+            ch.number(),
+            u16::from(*value) as i32 - 8192
+        ),
+        MidiMessage::ProgramChange(ch, program) => {
+            format!("ch{} ProgramChange {}", ch.number(), u8::from(*program))
+        }
+        MidiMessage::OwnedSysEx(data) => {
+            let data: Vec<_> = data
+                .iter()
+                .map(|b| format!(" {:02X}", u8::from(*b)))
+                .collect();
+            format!("SysEx F0{} F7", data.concat())
+        }
+        other => format!("{:?}", other),
+    }
+}
+
+/// Picks the port matching `wanted` out of `names`: an exact name, `@N` for
+/// the Nth port in enumeration order (as shown by `--list-ports`), or else the
+/// first port whose name contains `wanted`, ignoring case.
+pub fn find_port(names: &[String], wanted: &str) -> eyre::Result<Option<usize>> {
+    if let Some(i) = names.iter().position(|n| n == wanted) {
+        return Ok(Some(i));
+    }
+    if let Some(index) = wanted.strip_prefix('@') {
+        if let Ok(i) = index.parse::<usize>() {
+            if i >= names.len() {
+                return Err(eyre::eyre!(
+                    "output port index {} is out of range, there are {} ports",
+                    wanted,
+                    names.len()
+                ));
+            }
+            return Ok(Some(i));
+        }
+    }
+    let wanted = wanted.to_lowercase();
+    let matches: Vec<usize> = (0..names.len())
+        .filter(|&i| names[i].to_lowercase().contains(&wanted))
+        .collect();
+    if matches.len() > 1 {
+        log::warn!(
+            "Output port name {:?} matches several ports, using the first: {:?}",
+            wanted,
+            matches.iter().map(|&i| &names[i]).collect::<Vec<_>>()
+        );
+    }
+    Ok(matches.first().copied())
+}
+
+/// Shifts `note` by `semitones`, clamped to the MIDI note range.
+fn transpose(note: Note, semitones: i16) -> u8 {
+    (u8::from(note) as i16 + semitones).clamp(0, 127) as u8
+}
+
+/// The in-scale note closest to `note`, preferring the lower one on a tie.
+/// `scale` holds semitone offsets from `root`'s pitch class, in any octave.
+fn quantize_to_scale(note: u8, root: Note, scale: &[u8]) -> Note {
+    let root = u8::from(root) % 12;
+    let in_scale = |n: u8| scale.iter().any(|s| (n + 12 - root) % 12 == s % 12);
+    let note = note.min(127);
+    (0..=127u8)
+        .filter(|n| in_scale(*n))
+        .min_by_key(|n| ((*n as i16 - note as i16).abs(), *n))
+        .map(Note::from_u8_lossy)
+        .unwrap_or_else(|| Note::from_u8_lossy(note))
+}
+
+/// Reads `x` in `0.0..=1.0` off an `axis_curve_table`, between the two
+/// entries it falls between.
+fn lookup(table: &[U7], x: f32) -> f32 {
+    let at = |i: usize| f32::from(u8::from(table[i])) / 127.0;
+    if table.len() < 2 {
+        return if table.is_empty() { x } else { at(0) };
+    }
+    let x = x.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+    let i = (x as usize).min(table.len() - 2);
+    at(i) + (at(i + 1) - at(i)) * (x - i as f32)
+}
+
+/// Snaps `pos` to 0.0 inside the deadzone and rescales the rest of the range so
+/// full deflection still reaches -1.0/1.0.
+fn apply_deadzone(pos: f32, deadzone: f32) -> f32 {
+    if pos.abs() < deadzone {
+        0.0
+    } else {
+        pos.signum() * (pos.abs() - deadzone) / (1.0 - deadzone)
+    }
+}
+
+/// Rounds `v` to a 7-bit value, clamping rather than failing on anything out
+/// of range. NaN comes out as 0.
+fn clamped_u7(v: f32) -> U7 {
+    U7::from_u8_lossy(v.round().clamp(0.0, 127.0) as u8)
+}
+
+/// `clamped_u7` for 14-bit values.
+fn clamped_u14(v: f32) -> U14 {
+    clamp_u14(v.round().clamp(0.0, 16383.0) as u16)
+}
+
+fn clamp_u14(v: u16) -> U14 {
+    U14::try_from(v.min(16383)).unwrap_or(U14::MAX)
+}
+
+fn abs_float_to_midi(pos: f32) -> U7 {
+    // Some drivers report slightly past the documented range.
+    clamped_u7(pos.clamp(0.0, 1.0) * 127.0)
+}
+
+fn centered_float_to_midi(pos: f32) -> U7 {
+    centered_around(pos, CC_CENTER)
+}
+
+/// Like `centered_float_to_midi`, resting at `center` instead of 64.
+fn centered_around(pos: f32, center: U7) -> U7 {
+    clamped_u7(f32::from(u8::from(center)) + pos.clamp(-1.0, 1.0) * 64.0)
+}
+
+/// Maps `v` from 0..=127 onto `min..=max`.
+fn rescale(v: U7, min: U7, max: U7) -> U7 {
+    let (min, max) = (u8::from(min), u8::from(max));
+    let v = u16::from(u8::from(v));
+    let span = u16::from(max - min);
+    U7::from_u8_lossy(min + ((v * span + 63) / 127) as u8)
+}
+
+/// Maps `v` from 0..=16383 onto `min..=max`.
+fn rescale_u14(v: U14, min: u16, max: u16) -> U14 {
+    let v = u32::from(u16::from(v));
+    let span = u32::from(max - min);
+    clamp_u14(min + ((v * span + 8191) / 16383) as u16)
+}
+
+fn abs_float_to_u14(pos: f32) -> U14 {
+    clamped_u14(pos.clamp(0.0, 1.0) * 16383.0)
+}
+
+fn centered_float_to_u14(pos: f32) -> U14 {
+    clamped_u14(8192.0 + pos.clamp(-1.0, 1.0) * 8192.0)
+}
+
+/// Selects NRPN `param` and sets it to `value`: CC 99/98 with the parameter
+/// number, then CC 6/38 with the data.
+fn nrpn_messages(channel: Channel, param: u16, value: U14) -> [MidiMessage<'static>; 4] {
+    let param = param.min(16383);
+    let param_msb = U7::from_u8_lossy((param >> 7) as u8);
+    let param_lsb = U7::from_u8_lossy((param & 0x7f) as u8);
+    let (data_msb, data_lsb) = split_u14(value);
+    [
+        MidiMessage::ControlChange(
+            channel,
+            ControlFunction::NON_REGISTERED_PARAMETER_NUMBER_MSB,
+            param_msb,
+        ),
+        MidiMessage::ControlChange(
+            channel,
+            ControlFunction::NON_REGISTERED_PARAMETER_NUMBER_LSB,
+            param_lsb,
+        ),
+        MidiMessage::ControlChange(channel, ControlFunction::DATA_ENTRY_MSB, data_msb),
+        MidiMessage::ControlChange(channel, ControlFunction::DATA_ENTRY_LSB, data_lsb),
+    ]
+}
+
+/// CCs whose meaning depends on the messages around them or that act as
+/// commands, so they skip the dedupe and the rate limit: Bank Select, data
+/// entry and (N)RPN selection, and channel mode messages.
+fn is_command_cc(cc: ControlFunction) -> bool {
+    matches!(u8::from(cc.0), 0 | 32 | 6 | 38 | 96..=101 | 120..=127)
+}
+
+/// Splits a 14-bit value into its (MSB, LSB) 7-bit halves.
+fn split_u14(value: wmidi::U14) -> (U7, U7) {
+    let v = u16::from(value);
+    (
+        U7::from_u8_lossy((v >> 7) as u8),
+        U7::from_u8_lossy((v & 0x7f) as u8),
+    )
+}
@@ -0,0 +1,134 @@
+//! `--log-file`: a copy of the log in a file as well as on stderr, for
+//! looking back over a session nobody was watching.
+
+use eyre::{Result, WrapErr};
+use log::LevelFilter;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// How big the file gets before it's moved aside for a fresh one.
+const MAX_BYTES: u64 = 4 << 20;
+/// How many moved aside files (`path.1` newest to `path.N` oldest) to keep.
+const KEEP: u32 = 3;
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path) -> Result<RotatingFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("can't write the log file {}", path.display()))?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(RotatingFile {
+            path: path.to_owned(),
+            file,
+            written,
+        })
+    }
+
+    fn aside(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        // Renaming over a file fails on Windows, so make room first.
+        let _ = std::fs::remove_file(self.aside(KEEP));
+        for n in (1..KEEP).rev() {
+            let from = self.aside(n);
+            if from.exists() {
+                std::fs::rename(&from, self.aside(n + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, self.aside(1))?;
+        self.file = File::create(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.written > 0 && self.written + line.len() as u64 > MAX_BYTES {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// The usual stderr logger, with everything it lets through also going to
+/// the file.
+struct Tee {
+    stderr: pretty_env_logger::env_logger::Logger,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl log::Log for Tee {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.stderr.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.stderr.matches(record) {
+            return;
+        }
+        self.stderr.log(record);
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+        let line = format!(
+            "{} {:5} {} > {}\n",
+            humantime::format_rfc3339_seconds(SystemTime::now()),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_line(&line) {
+            // Logging it would only come back here.
+            eprintln!("Writing {}: {}", file.path.display(), e);
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap_or_else(|e| e.into_inner()).file.flush();
+        }
+    }
+}
+
+/// Sets up logging to stderr and to `log_file` if there is one. Warnings and
+/// errors are logged, each `-v` in `verbose` adds a level (info, debug, then
+/// trace), and `RUST_LOG` applies on top of that as always.
+pub fn init(log_file: Option<&Path>, verbose: u8) -> Result<()> {
+    let mut builder = pretty_env_logger::formatted_builder();
+    builder.filter_level(match verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    });
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    let stderr = builder.build();
+    let file = match log_file {
+        Some(path) => Some(Mutex::new(RotatingFile::open(path)?)),
+        None => None,
+    };
+    log::set_max_level(stderr.filter());
+    log::set_boxed_logger(Box::new(Tee { stderr, file }))?;
+    Ok(())
+}
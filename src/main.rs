@@ -1,162 +1,800 @@
-use eyre::{eyre, Result};
-use gilrs::{Axis, Button, Event, EventType, Gilrs};
-use std::collections::HashMap;
-use std::convert::TryInto;
-use std::default::Default;
-use wmidi::{Channel, ControlValue, MidiMessage, Note, ControlFunction};
-
-#[derive(Clone, Debug)]
-pub struct Config {
-    output_port_name: String,
-    output_midi_channel: Channel,
-    keys: HashMap<Button, Note>,
-    analog_button_ccs: HashMap<Button, ControlValue>,
-    axis_ccs: HashMap<Axis, ControlValue>,
-}
-
-impl Default for Config {
-    fn default() -> Config {
-        let mut cfg = Config {
-            output_port_name: "xbox".to_string(),
-            output_midi_channel: Channel::Ch15,
-            keys: HashMap::new(),
-            analog_button_ccs: HashMap::new(),
-            axis_ccs: HashMap::new(),
-        };
-        cfg.keys.extend(vec![
-            (Button::North, Note::C1),
-            (Button::East, Note::D1),
-            (Button::South, Note::E1),
-            (Button::West, Note::F1),
-            (Button::LeftTrigger, Note::A2),
-            (Button::RightTrigger, Note::B2),
-            (Button::Start, Note::C3),
-            (Button::Select, Note::D3),
-            (Button::Mode, Note::E3),
-            (Button::DPadUp, Note::A4),
-            (Button::DPadDown, Note::B4),
-            (Button::DPadLeft, Note::C4),
-            (Button::DPadRight, Note::D4),
-        ]);
-        cfg.analog_button_ccs.extend(vec![
-            (Button::LeftTrigger2, 1_u8.try_into().unwrap()),
-            (Button::RightTrigger2, 2_u8.try_into().unwrap()),
-        ]);
-        cfg.axis_ccs.extend(vec![
-            (Axis::LeftStickX, 3_u8.try_into().unwrap()),
-            (Axis::LeftStickY, 4_u8.try_into().unwrap()),
-            (Axis::RightStickX, 5_u8.try_into().unwrap()),
-            (Axis::RightStickY, 6_u8.try_into().unwrap()),
-        ]);
-        cfg
+use eyre::{eyre, Result, WrapErr};
+use gamepad2midi::{
+    describe_message, find_port, is_midi, map_event, osc, smf, Config, EventClock, Route, Rumble,
+    State,
+};
+use gilrs::ev::filter::{Filter, Repeat};
+use gilrs::{Event, EventType, GamepadId, Gilrs};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use wmidi::{MidiMessage, Note, U7};
+
+mod calibrate;
+mod json;
+mod logfile;
+mod ws;
+
+const DEFAULT_CONFIG_PATH: &str = "gamepad2midi.toml";
+
+#[derive(Debug, Default)]
+struct Args {
+    config: Option<PathBuf>,
+    list_ports: bool,
+    list_gamepads: bool,
+    create_virtual: bool,
+    print_default_config: bool,
+    record: Option<PathBuf>,
+    dry_run: bool,
+    json: bool,
+    ws: Option<u16>,
+    gamepads: Vec<usize>,
+    log_file: Option<PathBuf>,
+    osc: Option<String>,
+    calibrate: bool,
+    /// Start from `Config::unmapped` rather than the built-in mapping.
+    no_default_mapping: bool,
+    /// How many times `-v` was given.
+    verbose: u8,
+}
+
+impl Args {
+    fn parse() -> Result<Args> {
+        let mut args = Args::default();
+        let mut it = std::env::args().skip(1);
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "--config" => {
+                    let path = it.next().ok_or_else(|| eyre!("--config needs a path"))?;
+                    args.config = Some(path.into());
+                }
+                "--list-ports" => args.list_ports = true,
+                "--list-gamepads" => args.list_gamepads = true,
+                "--virtual" => args.create_virtual = true,
+                "--print-default-config" => args.print_default_config = true,
+                "--dry-run" => args.dry_run = true,
+                "--calibrate" => args.calibrate = true,
+                "--no-default-mapping" => args.no_default_mapping = true,
+                "--json" => args.json = true,
+                "--record" => {
+                    let path = it.next().ok_or_else(|| eyre!("--record needs a path"))?;
+                    args.record = Some(path.into());
+                }
+                "--osc" => {
+                    let target = it.next().ok_or_else(|| eyre!("--osc needs a host:port"))?;
+                    args.osc = Some(target);
+                }
+                "--gamepad" => {
+                    let id = it.next().ok_or_else(|| eyre!("--gamepad needs an id"))?;
+                    let id = id.parse().wrap_err("--gamepad needs an id number")?;
+                    args.gamepads.push(id);
+                }
+                "--ws" => {
+                    let port = it.next().ok_or_else(|| eyre!("--ws needs a port"))?;
+                    let port = port.parse().wrap_err("--ws needs a port number")?;
+                    args.ws = Some(port);
+                }
+                "--log-file" => {
+                    let path = it.next().ok_or_else(|| eyre!("--log-file needs a path"))?;
+                    args.log_file = Some(path.into());
+                }
+                "--verbose" => args.verbose += 1,
+                v if v.len() > 1 && v.starts_with('-') && v[1..].bytes().all(|b| b == b'v') => {
+                    args.verbose += (v.len() - 1) as u8;
+                }
+                other => return Err(eyre!("unrecognized argument: {}", other)),
+            }
+        }
+        Ok(args)
     }
 }
 
-fn main() -> Result<()> {
-    pretty_env_logger::init();
-    let mut gilrs = Gilrs::new().map_err(|e| eyre!("{}", e))?;
-    let midi_out = midir::MidiOutput::new("gamepad2midi")?;
-    let cfg = Config::default();
-    log::info!("Config: {:#?}", cfg);
-    let mut connection = None;
+/// Opens gamepad input, retrying as `gilrs_init_attempts` says.
+fn init_gilrs(cfg: &Config) -> Result<Gilrs> {
+    let mut delay = Duration::from_millis(cfg.gilrs_init_retry_ms);
+    let mut attempt = 1;
+    loop {
+        log::info!(
+            "Opening gamepad input, attempt {} of {}",
+            attempt,
+            cfg.gilrs_init_attempts
+        );
+        match Gilrs::new() {
+            Ok(gilrs) => return Ok(gilrs),
+            Err(e) if attempt >= cfg.gilrs_init_attempts => return Err(eyre!("{}", e)),
+            Err(e) => {
+                log::warn!(
+                    "Couldn't open gamepad input: {}, retrying in {:?}",
+                    e,
+                    delay
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+fn list_ports(midi_out: &midir::MidiOutput) -> Result<()> {
+    println!("MIDI output ports:");
+    let ports = midi_out.ports();
+    if ports.is_empty() {
+        println!("  none found");
+    }
+    for (i, port) in ports.iter().enumerate() {
+        println!("  {}: {}", i, midi_out.port_name(port)?);
+    }
+    Ok(())
+}
+
+/// Whether to listen to a gamepad, going by `allowed_gamepads` and the
+/// `--gamepad` ids. A pad has to pass both, where they're given.
+fn gamepad_wanted(cfg: &Config, ids: &[usize], id: GamepadId, name: &str) -> bool {
+    let name = name.to_lowercase();
+    (ids.is_empty() || ids.contains(&id.into()))
+        && cfg.allowed_gamepads.as_ref().is_none_or(|allowed| {
+            allowed
+                .iter()
+                .any(|wanted| name.contains(&wanted.to_lowercase()))
+        })
+}
+
+/// Warns when there's no gamepad yet, or when none of the connected ones are
+/// wanted, which is likely a typo rather than a pad that's yet to be plugged
+/// in.
+fn warn_if_no_gamepads(cfg: &Config, ids: &[usize], gilrs: &Gilrs) {
+    let connected: Vec<_> = gilrs
+        .gamepads()
+        .map(|(id, gamepad)| (id, gamepad.name().to_string()))
+        .collect();
+    if connected.is_empty() {
+        // Not an error: it'll be picked up when it's plugged in.
+        log::warn!("No gamepads connected, waiting for one to be plugged in");
+        return;
+    }
+    if ids.is_empty() && cfg.allowed_gamepads.is_none() {
+        return;
+    }
+    if connected
+        .iter()
+        .any(|(id, name)| gamepad_wanted(cfg, ids, *id, name))
+    {
+        return;
+    }
+    let available: String = connected
+        .iter()
+        .map(|(id, name)| format!("\n  {}: {}", id, name))
+        .collect();
+    log::warn!(
+        "None of the connected gamepads are selected, connected gamepads:{}",
+        available
+    );
+}
+
+fn list_gamepads(gilrs: &Gilrs) {
+    println!("Gamepads:");
+    let mut any = false;
+    for (id, gamepad) in gilrs.gamepads() {
+        println!("  {}: {}", id, gamepad.name());
+        any = true;
+    }
+    if !any {
+        println!("  none found");
+    }
+}
 
-    for mop in midi_out.ports().iter() {
-        let pn = midi_out.port_name(mop)?;
+/// Connects to the port named by `output_port_name`, falling back to a virtual
+/// port when one was requested by the config or `--virtual`.
+fn connect_output(
+    midi_out: midir::MidiOutput,
+    cfg: &Config,
+    create_virtual: bool,
+) -> Result<midir::MidiOutputConnection> {
+    let ports = midi_out.ports();
+    let names = ports
+        .iter()
+        .map(|port| midi_out.port_name(port))
+        .collect::<Result<Vec<_>, _>>()?;
+    for pn in names.iter() {
         log::info!("Output port: {}", pn);
-        if pn == cfg.output_port_name {
-            connection = Some(midi_out.connect(mop, "gamepad2midi")?);
-            break;
+    }
+    if let Some(i) = find_port(&names, &cfg.output_port_name)? {
+        log::info!("Connecting to output port {}", names[i]);
+        return midi_out
+            .connect(&ports[i], "gamepad2midi")
+            .map_err(|e| eyre!("{}", e));
+    }
+    if create_virtual || cfg.create_virtual {
+        log::info!(
+            "No output port named {}, creating virtual port gamepad2midi",
+            cfg.output_port_name
+        );
+        return connect_virtual(midi_out);
+    }
+    let candidates = if names.is_empty() {
+        " (none)".to_string()
+    } else {
+        names.iter().map(|n| format!("\n  {}", n)).collect()
+    };
+    Err(eyre!(
+        "no MIDI output port named {:?}, available ports:{}",
+        cfg.output_port_name,
+        candidates
+    ))
+}
+
+/// Listens on an input port, handing what comes in to `tx`, unless it's
+/// one that `refuse` says mustn't be listened to.
+fn connect_input(
+    wanted: &str,
+    refuse: impl Fn(&str) -> bool,
+    tx: mpsc::Sender<Vec<u8>>,
+) -> Result<midir::MidiInputConnection<mpsc::Sender<Vec<u8>>>> {
+    let midi_in = midir::MidiInput::new("gamepad2midi")?;
+    let ports = midi_in.ports();
+    let names = ports
+        .iter()
+        .map(|port| midi_in.port_name(port))
+        .collect::<Result<Vec<_>, _>>()?;
+    let i =
+        find_port(&names, wanted)?.ok_or_else(|| eyre!("no MIDI input port named {:?}", wanted))?;
+    if refuse(&names[i]) {
+        return Err(eyre!(
+            "not listening on {}, it's a port we send to and it would all come back round",
+            names[i]
+        ));
+    }
+    log::info!("Listening on input port {}", names[i]);
+    // The callback runs on midir's thread, and gilrs and the output
+    // connections stay on this one.
+    let callback = |_, bytes: &[u8], tx: &mut mpsc::Sender<Vec<u8>>| {
+        let _ = tx.send(bytes.to_vec());
+    };
+    midi_in
+        .connect(&ports[i], "gamepad2midi", callback, tx)
+        .map_err(|e| eyre!("{}", e))
+}
+
+/// Rumbles every `wanted` gamepad that can for a NoteOn from the `rumble`
+/// input. The effect stops if it's dropped, so it's returned for the caller
+/// to hold on to until the next one.
+fn rumble(
+    gilrs: &mut Gilrs,
+    rumble: &Rumble,
+    note: Note,
+    velocity: U7,
+    wanted: impl Fn(GamepadId, &str) -> bool,
+) -> Result<Option<gilrs::ff::Effect>> {
+    let effects = rumble.effects(note, velocity);
+    let pads: Vec<_> = gilrs
+        .gamepads()
+        .filter(|(id, gamepad)| gamepad.is_ff_supported() && wanted(*id, gamepad.name()))
+        .map(|(id, _)| id)
+        .collect();
+    if effects.is_empty() || pads.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = gilrs::ff::EffectBuilder::new();
+    for effect in effects {
+        builder.add_effect(effect);
+    }
+    let effect = builder
+        .gamepads(&pads)
+        .repeat(gilrs::ff::Repeat::For(gilrs::ff::Ticks::from_ms(
+            rumble.duration_ms,
+        )))
+        .finish(gilrs)
+        .map_err(|e| eyre!("{}", e))?;
+    effect.play().map_err(|e| eyre!("{}", e))?;
+    Ok(Some(effect))
+}
+
+/// The names of the output ports the config sends to, which are likely to
+/// be the same as the names of their input sides.
+fn sent_to_ports(cfg: &Config) -> Result<Vec<String>> {
+    let midi_out = midir::MidiOutput::new("gamepad2midi")?;
+    let names = midi_out
+        .ports()
+        .iter()
+        .map(|port| midi_out.port_name(port))
+        .collect::<Result<Vec<_>, _>>()?;
+    let wanted = std::iter::once(&cfg.output_port_name).chain(cfg.outputs.iter().map(|r| &r.port));
+    let mut sent_to = Vec::new();
+    for wanted in wanted {
+        if let Some(i) = find_port(&names, wanted)? {
+            sent_to.push(names[i].clone());
+        }
+    }
+    Ok(sent_to)
+}
+
+/// Connects to the port `wanted` names, if there is one, for `outputs`
+/// entries and for getting back a port that's gone.
+fn connect_port(wanted: &str) -> Result<Option<midir::MidiOutputConnection>> {
+    let midi_out = midir::MidiOutput::new("gamepad2midi")?;
+    let ports = midi_out.ports();
+    let names = ports
+        .iter()
+        .map(|port| midi_out.port_name(port))
+        .collect::<Result<Vec<_>, _>>()?;
+    match find_port(&names, wanted)? {
+        Some(i) => {
+            log::info!("Connecting to output port {}", names[i]);
+            let connection = midi_out
+                .connect(&ports[i], "gamepad2midi")
+                .map_err(|e| eyre!("{}", e))?;
+            Ok(Some(connection))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(unix)]
+fn connect_virtual(midi_out: midir::MidiOutput) -> Result<midir::MidiOutputConnection> {
+    use midir::os::unix::VirtualOutput;
+    midi_out
+        .create_virtual("gamepad2midi")
+        .map_err(|e| eyre!("{}", e))
+}
+
+#[cfg(not(unix))]
+fn connect_virtual(_midi_out: midir::MidiOutput) -> Result<midir::MidiOutputConnection> {
+    Err(eyre!(
+        "virtual MIDI ports are not supported on this platform"
+    ))
+}
+
+/// Watches the directory holding `path` (editors often replace files rather
+/// than write them in place) and signals `tx` whenever `path` changes.
+fn watch_config(path: &Path, tx: mpsc::Sender<()>) -> notify::Result<notify::RecommendedWatcher> {
+    use notify::Watcher;
+    let file_name = path.file_name().map(|n| n.to_owned());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+        _ => PathBuf::from("."),
+    };
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            if event
+                .paths
+                .iter()
+                .any(|p| p.file_name() == file_name.as_deref())
+            {
+                let _ = tx.send(());
+            }
+        }
+    })?;
+    watcher.watch(&dir, notify::RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+/// Sends `bytes` if `connection` is there, and drops the connection if
+/// that fails so `Output::reconnect` can have another go.
+fn send_or_drop(connection: &mut Option<midir::MidiOutputConnection>, port: &str, bytes: &[u8]) {
+    let result = match connection {
+        Some(c) => c.send(bytes),
+        None => return,
+    };
+    match result {
+        Ok(()) => {}
+        // Only `raw_button_bytes` can get this far with bytes the port
+        // won't take, which says nothing about the port itself.
+        Err(midir::SendError::InvalidData(e)) => {
+            log::warn!("{} wouldn't take {:02X?}: {}", port, bytes, e);
+        }
+        Err(e) => {
+            log::error!("Lost {}, will keep trying to reconnect: {}", port, e);
+            *connection = None;
         }
     }
+}
+
+/// How long to wait between attempts to get a lost port back.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
 
+/// Where outgoing messages go: the MIDI port (or stdout on a dry run), and
+/// the recording if there is one.
+struct Output {
+    /// `None` on a dry run, or while the port is gone.
+    connection: Option<midir::MidiOutputConnection>,
+    /// `Config::outputs` as it was at startup, with the connection to each
+    /// (`None` while its port isn't there).
+    routes: Vec<(Route, Option<midir::MidiOutputConnection>)>,
+    dry_run: bool,
+    /// `--json`/`--ws` lines for what's sent. On stdout they take the place
+    /// of the dry run printout.
+    json: json::Sink,
+    next_reconnect: Instant,
+    recorder: Option<smf::Recorder>,
+    buf: Vec<u8>,
+}
+
+impl Output {
+    /// Tries to get back any port that's gone, or wasn't there to begin
+    /// with. Whatever would have gone to it in the meantime is dropped.
+    fn reconnect(&mut self, cfg: &Config) {
+        let now = Instant::now();
+        if self.dry_run || now < self.next_reconnect {
+            return;
+        }
+        self.next_reconnect = now + RECONNECT_INTERVAL;
+        let wanted = std::iter::once((&cfg.output_port_name, &mut self.connection))
+            .chain(self.routes.iter_mut().map(|(r, c)| (&r.port, c)))
+            .filter(|(_, connection)| connection.is_none());
+        for (port, connection) in wanted {
+            match connect_port(port) {
+                Ok(Some(c)) => {
+                    log::info!("Output port {:?} is back", port);
+                    *connection = Some(c);
+                }
+                Ok(None) => {}
+                Err(e) => log::debug!("Reconnecting to {:?}: {:#}", port, e),
+            }
+        }
+    }
+
+    /// Sends `mm`, recording it as sent `at`. `cause` says what produced it,
+    /// for the dry run printout.
+    fn send(&mut self, mm: &MidiMessage, at: Instant, cause: impl std::fmt::Display) -> Result<()> {
+        log::debug!("Would send: {:?}", mm);
+        self.buf.clear();
+        self.buf.resize(mm.bytes_size(), 0);
+        mm.copy_to_slice(&mut self.buf)?;
+        let mut routed = false;
+        for (route, connection) in self.routes.iter_mut() {
+            if !route.takes(mm) {
+                continue;
+            }
+            routed = true;
+            if self.dry_run && !self.json.stdout {
+                println!("{} -> {}: {}", cause, route.port, describe_message(mm));
+            } else if !self.dry_run {
+                send_or_drop(connection, &route.port, &self.buf);
+            }
+        }
+        if !routed {
+            if self.dry_run && !self.json.stdout {
+                println!("{} -> {}", cause, describe_message(mm));
+            } else if !self.dry_run {
+                send_or_drop(&mut self.connection, "the output port", &self.buf);
+            }
+        }
+        self.json.midi(cause, &self.buf, describe_message(mm));
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(&self.buf, at);
+        }
+        Ok(())
+    }
+
+    /// Sends `raw_button_bytes` as they are to the output port. They're only
+    /// recorded if they read as MIDI, which a file has no room for otherwise.
+    fn send_raw(&mut self, bytes: &[u8], at: Instant, cause: impl std::fmt::Display) {
+        let description = format!("raw bytes {:02X?}", bytes);
+        log::debug!("Would send: {}", description);
+        if self.dry_run && !self.json.stdout {
+            println!("{} -> {}", cause, description);
+        } else if !self.dry_run {
+            send_or_drop(&mut self.connection, "the output port", bytes);
+        }
+        self.json.midi(cause, bytes, description);
+        if let Some(recorder) = &mut self.recorder {
+            if is_midi(bytes) {
+                recorder.record(bytes, at);
+            }
+        }
+    }
+
+    /// Sends whatever in `out` is worth sending as of `now`, emptying it.
+    fn send_all(
+        &mut self,
+        cfg: &Config,
+        state: &mut State,
+        out: &mut Vec<MidiMessage>,
+        now: Instant,
+        cause: impl std::fmt::Display,
+    ) -> Result<()> {
+        for mm in out.drain(..) {
+            let (ahead, mm) = match state.outgoing(cfg, mm, now) {
+                Some(outgoing) => outgoing,
+                None => continue,
+            };
+            for before in ahead.iter() {
+                let why = match before {
+                    MidiMessage::NoteOff(..) => "voice stealing",
+                    _ => "MPE channel reset",
+                };
+                self.send(before, now, format_args!("{} ({})", cause, why))?;
+            }
+            self.send(&mm, now, &cause)?;
+        }
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse()?;
+    logfile::init(args.log_file.as_deref(), args.verbose)?;
+    let load = if args.no_default_mapping {
+        Config::load_unmapped
+    } else {
+        Config::load
+    };
+    if args.print_default_config {
+        let cfg = if args.no_default_mapping {
+            Config::unmapped()
+        } else {
+            Config::default()
+        };
+        print!("{}", cfg.to_toml()?);
+        return Ok(());
+    }
+    if args.list_ports || args.list_gamepads {
+        if args.list_ports {
+            list_ports(&midir::MidiOutput::new("gamepad2midi")?)?;
+        }
+        list_gamepads(&Gilrs::new().map_err(|e| eyre!("{}", e))?);
+        return Ok(());
+    }
+    let config_path = match args.config {
+        Some(path) => Some(path),
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => Some(DEFAULT_CONFIG_PATH.into()),
+        None => None,
+    };
+    let mut cfg = match &config_path {
+        Some(path) => load(path)?,
+        None if args.no_default_mapping => {
+            log::warn!(
+                "No {} found and --no-default-mapping given, nothing is mapped",
+                DEFAULT_CONFIG_PATH
+            );
+            Config::unmapped()
+        }
+        None => {
+            log::info!(
+                "No {} found, using the built-in mapping",
+                DEFAULT_CONFIG_PATH
+            );
+            Config::default()
+        }
+    };
+    log::info!("Config: {:#?}", cfg);
+    if args.calibrate {
+        let mut gilrs = init_gilrs(&cfg)?;
+        let ids = &args.gamepads;
+        let found = calibrate::run(&mut gilrs, |id, name| gamepad_wanted(&cfg, ids, id, name));
+        let path = config_path.unwrap_or_else(|| DEFAULT_CONFIG_PATH.into());
+        return calibrate::save(&path, &found);
+    }
+    let (reload_tx, reload_rx) = mpsc::channel();
+    let _watcher = match &config_path {
+        Some(path) => match watch_config(path, reload_tx) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                log::warn!("Not watching {} for changes: {}", path.display(), e);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut gilrs = init_gilrs(&cfg)?;
+    let connection = if args.dry_run {
+        None
+    } else {
+        let midi_out = midir::MidiOutput::new("gamepad2midi")?;
+        Some(connect_output(midi_out, &cfg, args.create_virtual)?)
+    };
+    let mut routes = Vec::new();
+    for route in cfg.outputs.iter() {
+        let connection = if args.dry_run {
+            None
+        } else {
+            connect_port(&route.port)?
+        };
+        if connection.is_none() && !args.dry_run {
+            // Not an error: its messages are dropped rather than sent
+            // somewhere unexpected until it turns up.
+            log::warn!(
+                "No output port named {:?}, dropping the messages routed to it",
+                route.port
+            );
+        }
+        routes.push((route.clone(), connection));
+    }
+    let (thru_tx, thru_rx) = mpsc::channel();
+    let _thru_input = match &cfg.thru_port {
+        Some(port) => {
+            let sent_to = sent_to_ports(&cfg)?;
+            // Our own virtual port shows up as an input too.
+            let refuse =
+                |name: &str| name.starts_with("gamepad2midi") || sent_to.iter().any(|s| s == name);
+            Some(connect_input(port, refuse, thru_tx)?)
+        }
+        None => None,
+    };
+    let osc_out = match args.osc.as_ref().or(cfg.osc.target.as_ref()) {
+        Some(target) => Some(osc::Sender::new(target)?),
+        None => None,
+    };
+    let mut output = Output {
+        connection,
+        routes,
+        dry_run: args.dry_run,
+        json: json::Sink {
+            stdout: args.json,
+            ws: match args.ws {
+                Some(port) => Some(ws::serve(port)?),
+                None => None,
+            },
+        },
+        next_reconnect: Instant::now(),
+        recorder: args.record.as_deref().map(smf::Recorder::new),
+        buf: Vec::new(),
+    };
+
+    let poll_interval = Duration::from_millis(cfg.poll_interval_ms);
+    let mut state = State::new(&cfg);
     for (id, gamepad) in gilrs.gamepads() {
         log::info!("id({:?}) {}", id, gamepad.name());
+        if gamepad_wanted(&cfg, &args.gamepads, id, gamepad.name()) {
+            state.channel_for(&cfg, id);
+            state.name_gamepad(&cfg, id, gamepad.name());
+        }
     }
-    let mut outbuf = Vec::new();
-    loop {
-        while let Some(Event { id, event, time }) = gilrs.next_event() {
-            if let Some(mm) = match event {
-                EventType::ButtonChanged(btn, pos, code) => {
-                    log::debug!("{:?} {} {:?} {} {}", time, id, btn, pos, code);
-                    if let Some(cc) = cfg.analog_button_ccs.get(&btn) {
-                        let mm = MidiMessage::ControlChange(
-                            cfg.output_midi_channel,
-                            ControlFunction::from(*cc),
-                            abs_float_to_midi(pos),
-                        );
-                        Some(mm)
-                    } else {
-                        None
+    warn_if_no_gamepads(&cfg, &args.gamepads, &gilrs);
+    state.on_connect(&cfg, Instant::now());
+    let (rumble_tx, rumble_rx) = mpsc::channel();
+    let _rumble_input = match &cfg.rumble {
+        Some(rumble) => Some(connect_input(&rumble.port, |_| false, rumble_tx)?),
+        None => None,
+    };
+    // Held only so the last effect isn't dropped before it's played out.
+    let mut _rumbling = None;
+    let mut out = Vec::new();
+    let mut event_clock = EventClock::default();
+    // gilrs only reports held buttons as repeating through this filter, at
+    // its own rate: after 500ms, then every 30ms.
+    let repeat = Repeat::new();
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let running = running.clone();
+        ctrlc::set_handler(move || running.store(false, Ordering::SeqCst))?;
+    }
+    while running.load(Ordering::SeqCst) {
+        output.reconnect(&cfg);
+        let changed = reload_rx.try_iter().count() > 0;
+        if let (true, Some(path)) = (changed, &config_path) {
+            match load(path) {
+                Ok(new_cfg) if new_cfg == cfg => {}
+                Ok(new_cfg) => {
+                    log::info!("Reloaded {}", path.display());
+                    log::debug!("Config: {:#?}", new_cfg);
+                    for mm in state.reload(&cfg, &new_cfg) {
+                        output.send(&mm, Instant::now(), "config reload")?;
                     }
-                }
-                EventType::ButtonPressed(btn, code) => {
-                    log::debug!("{:?} {} {:?} press {}", time, id, btn, code);
-                    if let Some(note) = cfg.keys.get(&btn) {
-                        let mm = MidiMessage::NoteOn(
-                            cfg.output_midi_channel,
-                            *note,
-                            80u8.try_into().unwrap(),
-                        );
-                        Some(mm)
-                    } else {
-                        None
+                    if new_cfg.outputs != cfg.outputs {
+                        log::warn!("outputs changed, restart to connect to the new ports");
                     }
+                    cfg = new_cfg;
+                    warn_if_no_gamepads(&cfg, &args.gamepads, &gilrs);
                 }
-                EventType::ButtonReleased(btn, code) => {
-                    log::debug!("{:?} {} {:?} press {}", time, id, btn, code);
-                    if let Some(note) = cfg.keys.get(&btn) {
-                        let mm = MidiMessage::NoteOff(
-                            cfg.output_midi_channel,
-                            *note,
-                            80u8.try_into().unwrap(),
-                        );
-                        Some(mm)
-                    } else {
-                        None
-                    }
+                Err(e) => log::error!("Keeping the previous config: {:#}", e),
+            }
+        }
+        for bytes in thru_rx.try_iter() {
+            match MidiMessage::try_from(&bytes[..]) {
+                Ok(mm) => output.send(&mm, Instant::now(), "thru")?,
+                Err(e) => log::debug!("Not passing on {:02X?} from thru: {:?}", bytes, e),
+            }
+        }
+        for bytes in rumble_rx.try_iter() {
+            let (note, velocity) = match MidiMessage::try_from(&bytes[..]) {
+                Ok(MidiMessage::NoteOn(_, note, velocity)) if velocity != U7::MIN => {
+                    (note, velocity)
                 }
-                EventType::AxisChanged(ax, pos, code) => {
-                    log::debug!("{:?} {} {:?} {} {}", time, id, ax, pos, code);
-                    if let Some(cc) = cfg.axis_ccs.get(&ax) {
-                        let mm = MidiMessage::ControlChange(
-                            cfg.output_midi_channel,
-                            ControlFunction::from(*cc),
-                            centered_float_to_midi(pos),
-                        );
-                        Some(mm)
-                    } else {
-                        None
-                    }
+                _ => continue,
+            };
+            if let Some(r) = &cfg.rumble {
+                let ids = &args.gamepads;
+                let wanted = |id, name: &str| gamepad_wanted(&cfg, ids, id, name);
+                match rumble(&mut gilrs, r, note, velocity, wanted) {
+                    Ok(Some(effect)) => _rumbling = Some(effect),
+                    Ok(None) => {}
+                    Err(e) => log::warn!("Couldn't rumble: {:#}", e),
                 }
-                other => {
-                    log::debug!("{:?} {} {:?}", time, id, other);
-                    None
+            }
+        }
+        if cfg.button_debounce_ms > 0 {
+            for event in state.unsettled(&cfg, &gilrs, Instant::now()) {
+                gilrs.insert_event(event);
+            }
+        }
+        let mut handled = 0;
+        while cfg.max_events_per_tick.is_none_or(|max| handled < max) {
+            let mut next = gilrs.next_event();
+            if !cfg.repeat_as_retrigger.is_empty() {
+                next = next.filter_ev(&repeat, &mut gilrs);
+            }
+            let ev = match next {
+                Some(ev) => ev,
+                None => break,
+            };
+            if let EventType::ButtonRepeated(..) = ev.event {
+                // The filter makes these up after `next_event`, so gilrs
+                // has to be told about them or it repeats on every call.
+                gilrs.update(&ev);
+            }
+            handled += 1;
+            let Event { id, event, time } = ev;
+            let at = event_clock.at(time, Instant::now());
+            if !gamepad_wanted(&cfg, &args.gamepads, id, gilrs.gamepad(id).name()) {
+                if event == EventType::Connected {
+                    log::info!("Ignoring gamepad {}: {}", id, gilrs.gamepad(id).name());
                 }
-            } {
-                log::debug!("Would send: {:?}", mm);
-                if let Some(ref mut mop) = connection {
-                    outbuf.clear();
-                    outbuf.resize(mm.bytes_size(), 0);
-                    mm.copy_to_slice(&mut outbuf)?;
-                    mop.send(&outbuf)?;
+                continue;
+            }
+            let edge = match event {
+                EventType::ButtonPressed(btn, _) => Some((btn, true)),
+                EventType::ButtonReleased(btn, _) => Some((btn, false)),
+                _ => None,
+            };
+            if let Some((btn, pressed)) = edge {
+                if !state.debounce(&cfg, (id, btn), pressed, at) {
+                    log::debug!("{:?} {} {:?} bounced", time, id, btn);
+                    continue;
                 }
             }
+            output.json.event(id, &event, time);
+            match event {
+                EventType::Connected => {
+                    log::info!("Gamepad {} connected: {}", id, gilrs.gamepad(id).name());
+                    state.name_gamepad(&cfg, id, gilrs.gamepad(id).name());
+                }
+                EventType::Disconnected => {
+                    log::info!("Gamepad {} disconnected: {}", id, gilrs.gamepad(id).name());
+                }
+                _ => {}
+            }
+            if let Some(osc_out) = &osc_out {
+                match event {
+                    EventType::ButtonChanged(btn, pos, _) => {
+                        osc_out.send(cfg.osc.button(id, btn), cfg.shape_button(btn, pos));
+                    }
+                    EventType::AxisChanged(ax, pos, _) => {
+                        let pos = cfg.shape_axis(ax, state.calibrate_axis(&cfg, ax, pos));
+                        osc_out.send(cfg.osc.axis(id, ax), pos);
+                    }
+                    _ => {}
+                }
+            }
+            let mut mapped = map_event(&cfg, &mut state, &ev, at);
+            output.send_all(
+                &cfg,
+                &mut state,
+                &mut mapped,
+                at,
+                format_args!("gamepad {} {:?}", id, event),
+            )?;
+            for bytes in state.take_raw() {
+                output.send_raw(&bytes, at, format_args!("gamepad {} {:?}", id, event));
+            }
+        }
+        let now = Instant::now();
+        state.tick(&cfg, now, &mut out);
+        output.send_all(&cfg, &mut state, &mut out, now, "timer")?;
+        // gilrs only offers a non-blocking next_event(), so back off once the
+        // queue is drained instead of spinning on it. Stopping at
+        // max_events_per_tick may have left some waiting.
+        if Some(handled) != cfg.max_events_per_tick {
+            std::thread::sleep(poll_interval);
         }
     }
-}
-
-fn abs_float_to_midi(pos: f32) -> wmidi::U7 {
-    let b = pos * 128.0;
-    let b = b as u8;
-    let b = b.max(0).min(127);
-    use std::convert::TryFrom;
-    wmidi::U7::try_from(b).unwrap()
-}
 
-fn centered_float_to_midi(pos: f32) -> wmidi::U7 {
-    let b = 64.0 + pos * 64.0;
-    let b = b as u8;
-    let b = b.max(0).min(127);
-    use std::convert::TryFrom;
-    wmidi::U7::try_from(b).unwrap()
+    log::info!("Shutting down, releasing held notes");
+    for mm in state.shutdown(&cfg) {
+        output.send(&mm, Instant::now(), "shutdown")?;
+    }
+    Ok(())
 }
@@ -0,0 +1,254 @@
+//! Serde adapters for the gilrs/wmidi types that show up in `Config`.
+//!
+//! None of these types implement serde themselves, so each adapter here is a
+//! marker used with `#[serde_as(as = "...")]` and composes with maps, sets and
+//! options the same way the plain types would.
+
+use gilrs::{Axis, Button};
+use serde::de::{self, Deserializer, Unexpected, Visitor};
+use serde::ser::Serializer;
+use serde::Deserialize;
+use serde_with::{DeserializeAs, SerializeAs};
+use std::convert::TryFrom;
+use std::fmt;
+use wmidi::{Channel, Note, U7};
+
+const BUTTONS: &[(Button, &str)] = &[
+    (Button::South, "South"),
+    (Button::East, "East"),
+    (Button::North, "North"),
+    (Button::West, "West"),
+    (Button::C, "C"),
+    (Button::Z, "Z"),
+    (Button::LeftTrigger, "LeftTrigger"),
+    (Button::LeftTrigger2, "LeftTrigger2"),
+    (Button::RightTrigger, "RightTrigger"),
+    (Button::RightTrigger2, "RightTrigger2"),
+    (Button::Select, "Select"),
+    (Button::Start, "Start"),
+    (Button::Mode, "Mode"),
+    (Button::LeftThumb, "LeftThumb"),
+    (Button::RightThumb, "RightThumb"),
+    (Button::DPadUp, "DPadUp"),
+    (Button::DPadDown, "DPadDown"),
+    (Button::DPadLeft, "DPadLeft"),
+    (Button::DPadRight, "DPadRight"),
+    (Button::Unknown, "Unknown"),
+];
+
+const AXES: &[(Axis, &str)] = &[
+    (Axis::LeftStickX, "LeftStickX"),
+    (Axis::LeftStickY, "LeftStickY"),
+    (Axis::LeftZ, "LeftZ"),
+    (Axis::RightStickX, "RightStickX"),
+    (Axis::RightStickY, "RightStickY"),
+    (Axis::RightZ, "RightZ"),
+    (Axis::DPadX, "DPadX"),
+    (Axis::DPadY, "DPadY"),
+    (Axis::Unknown, "Unknown"),
+];
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+pub fn button_name(btn: Button) -> &'static str {
+    BUTTONS
+        .iter()
+        .find(|(b, _)| *b == btn)
+        .map(|(_, name)| *name)
+        .unwrap_or("Unknown")
+}
+
+pub fn parse_button(name: &str) -> Option<Button> {
+    BUTTONS.iter().find(|(_, n)| *n == name).map(|(b, _)| *b)
+}
+
+pub fn axis_name(axis: Axis) -> &'static str {
+    AXES.iter()
+        .find(|(a, _)| *a == axis)
+        .map(|(_, name)| *name)
+        .unwrap_or("Unknown")
+}
+
+pub fn parse_axis(name: &str) -> Option<Axis> {
+    AXES.iter().find(|(_, n)| *n == name).map(|(a, _)| *a)
+}
+
+/// Formats a note as a letter, optional sharp and octave, e.g. `C#3`.
+pub fn note_name(note: Note) -> String {
+    let n = u8::from(note);
+    format!("{}{}", NOTE_NAMES[(n % 12) as usize], (n / 12) as i8 - 1)
+}
+
+/// Parses a note name like `C3`, `F#-1` or `Bb4`. Middle C is `C4` (60).
+pub fn parse_note(name: &str) -> Option<Note> {
+    let mut chars = name.chars();
+    let pitch_class: i16 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = if let Some(o) = rest.strip_prefix('#') {
+        (1, o)
+    } else if let Some(o) = rest.strip_prefix('b') {
+        (-1, o)
+    } else {
+        (0, rest)
+    };
+    let octave: i16 = octave.parse().ok()?;
+    let n = (octave + 1) * 12 + pitch_class + accidental;
+    u8::try_from(n).ok().and_then(|n| Note::try_from(n).ok())
+}
+
+/// A `Button`, written as its gilrs variant name (`South`, `DPadUp`, ...).
+pub struct ButtonName;
+
+impl SerializeAs<Button> for ButtonName {
+    fn serialize_as<S: Serializer>(source: &Button, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(button_name(*source))
+    }
+}
+
+impl<'de> DeserializeAs<'de, Button> for ButtonName {
+    fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Button, D::Error> {
+        struct V;
+        impl<'de> Visitor<'de> for V {
+            type Value = Button;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a gamepad button name such as \"South\" or \"DPadUp\"")
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Button, E> {
+                parse_button(v).ok_or_else(|| E::invalid_value(Unexpected::Str(v), &self))
+            }
+        }
+        deserializer.deserialize_str(V)
+    }
+}
+
+/// An `Axis`, written as its gilrs variant name (`LeftStickX`, ...).
+pub struct AxisName;
+
+impl SerializeAs<Axis> for AxisName {
+    fn serialize_as<S: Serializer>(source: &Axis, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(axis_name(*source))
+    }
+}
+
+impl<'de> DeserializeAs<'de, Axis> for AxisName {
+    fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Axis, D::Error> {
+        struct V;
+        impl<'de> Visitor<'de> for V {
+            type Value = Axis;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a gamepad axis name such as \"LeftStickX\"")
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Axis, E> {
+                parse_axis(v).ok_or_else(|| E::invalid_value(Unexpected::Str(v), &self))
+            }
+        }
+        deserializer.deserialize_str(V)
+    }
+}
+
+/// A `Note`, written as a name like `"C3"`/`"F#2"`, or read from a raw MIDI note number.
+pub struct NoteName;
+
+impl SerializeAs<Note> for NoteName {
+    fn serialize_as<S: Serializer>(source: &Note, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&note_name(*source))
+    }
+}
+
+impl<'de> DeserializeAs<'de, Note> for NoteName {
+    fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Note, D::Error> {
+        struct V;
+        impl<'de> Visitor<'de> for V {
+            type Value = Note;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a note name such as \"C3\" or \"F#2\", or a MIDI note number 0..=127")
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Note, E> {
+                parse_note(v).ok_or_else(|| E::invalid_value(Unexpected::Str(v), &self))
+            }
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Note, E> {
+                u8::try_from(v)
+                    .ok()
+                    .and_then(|n| Note::try_from(n).ok())
+                    .ok_or_else(|| E::invalid_value(Unexpected::Signed(v), &self))
+            }
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Note, E> {
+                u8::try_from(v)
+                    .ok()
+                    .and_then(|n| Note::try_from(n).ok())
+                    .ok_or_else(|| E::invalid_value(Unexpected::Unsigned(v), &self))
+            }
+        }
+        deserializer.deserialize_any(V)
+    }
+}
+
+/// A 7-bit value such as a CC number, velocity or CC value, written as a
+/// plain integer. Every 7-bit number in the config is read through this, so
+/// they're all checked in one place and fail at load with the same error.
+pub struct ControlNumber;
+
+impl SerializeAs<U7> for ControlNumber {
+    fn serialize_as<S: Serializer>(source: &U7, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(u8::from(*source))
+    }
+}
+
+impl<'de> DeserializeAs<'de, U7> for ControlNumber {
+    fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<U7, D::Error> {
+        let v = i64::deserialize(deserializer)?;
+        u8::try_from(v)
+            .ok()
+            .and_then(|n| U7::try_from(n).ok())
+            .ok_or_else(|| de::Error::custom(format!("{} is out of range, must be 0..=127", v)))
+    }
+}
+
+/// A MIDI `Channel`, written as its 1-based number (1..=16).
+pub struct ChannelNumber;
+
+impl SerializeAs<Channel> for ChannelNumber {
+    fn serialize_as<S: Serializer>(source: &Channel, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(source.number())
+    }
+}
+
+impl<'de> DeserializeAs<'de, Channel> for ChannelNumber {
+    fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Channel, D::Error> {
+        let v = i64::deserialize(deserializer)?;
+        u8::try_from(v - 1)
+            .ok()
+            .and_then(|i| Channel::from_index(i).ok())
+            .ok_or_else(|| {
+                de::Error::custom(format!("channel {} is out of range, must be 1..=16", v))
+            })
+    }
+}
+
+/// An `f32` written with the fewest digits that read back as the same value,
+/// so `0.08` doesn't come out as `0.07999999821186066`.
+pub struct ShortFloat;
+
+impl SerializeAs<f32> for ShortFloat {
+    fn serialize_as<S: Serializer>(source: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+        let v: f64 = source.to_string().parse().unwrap_or(*source as f64);
+        serializer.serialize_f64(v)
+    }
+}
+
+impl<'de> DeserializeAs<'de, f32> for ShortFloat {
+    fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+        f32::deserialize(deserializer)
+    }
+}
@@ -0,0 +1,55 @@
+//! Sending gamepad events as OSC over UDP, for software that would rather
+//! have full resolution floats than MIDI.
+
+use eyre::{eyre, Result, WrapErr};
+use rosc::{OscMessage, OscPacket, OscType};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+pub struct Sender {
+    socket: UdpSocket,
+    target: SocketAddr,
+}
+
+impl Sender {
+    /// Sets up sending to `target`, a `host:port`.
+    pub fn new(target: &str) -> Result<Sender> {
+        let addr = target
+            .to_socket_addrs()
+            .wrap_err_with(|| format!("looking up OSC target {}", target))?
+            .next()
+            .ok_or_else(|| eyre!("OSC target {} has no address", target))?;
+        let local = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).wrap_err("opening a socket for OSC")?;
+        log::info!("Sending OSC to {}", addr);
+        Ok(Sender {
+            socket,
+            target: addr,
+        })
+    }
+
+    /// Sends `value` to `addr`. Failures are logged rather than returned,
+    /// UDP being fire and forget anyway.
+    pub fn send(&self, addr: String, value: f32) {
+        let packet = OscPacket::Message(OscMessage {
+            addr,
+            args: vec![OscType::Float(value)],
+        });
+        let sent = rosc::encoder::encode(&packet)
+            .map_err(|e| eyre!("{:?}", e))
+            .and_then(|bytes| Ok(self.socket.send_to(&bytes, self.target)?));
+        if let Err(e) = sent {
+            log::warn!("Sending OSC: {:#}", e);
+        }
+    }
+}
+
+/// Fills the `{id}` and `{name}` placeholders in an address pattern.
+pub fn address(pattern: &str, id: usize, name: &str) -> String {
+    pattern
+        .replace("{id}", &id.to_string())
+        .replace("{name}", name)
+}
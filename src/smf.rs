@@ -0,0 +1,111 @@
+//! Just enough of the Standard MIDI File format to save what was sent.
+
+use eyre::{Result, WrapErr};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use wmidi::MidiMessage;
+
+/// Ticks per quarter note. At the default 120bpm a quarter note is 500ms, so
+/// this makes one tick a millisecond.
+const DIVISION: u16 = 500;
+
+/// Collects messages into a single-track (format 0) file, which is written
+/// when the recorder is dropped so that every way out of `main` leaves a
+/// playable file behind.
+pub struct Recorder {
+    path: PathBuf,
+    start: Instant,
+    last_tick: u64,
+    track: Vec<u8>,
+}
+
+impl Recorder {
+    pub fn new(path: &Path) -> Recorder {
+        let mut track = Vec::new();
+        // Spell out the tempo DIVISION assumes rather than rely on the default.
+        track.extend_from_slice(&[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20]);
+        Recorder {
+            path: path.to_owned(),
+            start: Instant::now(),
+            last_tick: 0,
+            track,
+        }
+    }
+
+    /// Appends the encoded messages in `bytes`, sent `at`. Each goes in as
+    /// an event of its own, since the file wants a delta time ahead of every
+    /// message; anything left over that isn't a whole message is dropped.
+    pub fn record(&mut self, mut bytes: &[u8], at: Instant) {
+        while let Ok(mm) = MidiMessage::try_from(bytes) {
+            let size = mm.bytes_size();
+            self.record_one(&bytes[..size], at);
+            bytes = &bytes[size..];
+        }
+    }
+
+    /// Appends one encoded message sent `at`. Anything stamped before the
+    /// message ahead of it goes in right alongside it.
+    fn record_one(&mut self, bytes: &[u8], at: Instant) {
+        let status = match bytes.first() {
+            Some(status) => *status,
+            None => return,
+        };
+        // Other system messages (clock, start/stop, ...) only make sense on a
+        // live port and have no place in a file.
+        if status > 0xF0 {
+            return;
+        }
+        let tick = at.saturating_duration_since(self.start).as_millis() as u64;
+        let tick = tick.max(self.last_tick);
+        let delta = (tick - self.last_tick).min(0x0FFF_FFFF) as u32;
+        self.last_tick = tick;
+        write_varint(&mut self.track, delta);
+        if status == 0xF0 {
+            // SysEx events carry their length after the F0.
+            self.track.push(0xF0);
+            write_varint(&mut self.track, (bytes.len() - 1) as u32);
+            self.track.extend_from_slice(&bytes[1..]);
+        } else {
+            self.track.extend_from_slice(bytes);
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut file = Vec::with_capacity(self.track.len() + 26);
+        file.extend_from_slice(b"MThd");
+        file.extend_from_slice(&6u32.to_be_bytes());
+        file.extend_from_slice(&0u16.to_be_bytes());
+        file.extend_from_slice(&1u16.to_be_bytes());
+        file.extend_from_slice(&DIVISION.to_be_bytes());
+        file.extend_from_slice(b"MTrk");
+        file.extend_from_slice(&(self.track.len() as u32 + 4).to_be_bytes());
+        file.extend_from_slice(&self.track);
+        file.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+        std::fs::write(&self.path, file)
+            .wrap_err_with(|| format!("writing {}", self.path.display()))
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        match self.save() {
+            Ok(()) => log::info!("Saved recording to {}", self.path.display()),
+            Err(e) => log::error!("{:#}", e),
+        }
+    }
+}
+
+/// Writes `v` as a variable-length quantity: 7 bits per byte, most
+/// significant first, with the high bit set on all but the last byte.
+fn write_varint(out: &mut Vec<u8>, v: u32) {
+    let mut shift = 21;
+    while shift > 0 && v >> shift == 0 {
+        shift -= 7;
+    }
+    while shift > 0 {
+        out.push(0x80 | ((v >> shift) as u8 & 0x7F));
+        shift -= 7;
+    }
+    out.push(v as u8 & 0x7F);
+}
@@ -0,0 +1,1901 @@
+//! `State`, what the mapping remembers between events, and `map_event`,
+//! which works out what each event sends.
+
+use crate::config::{
+    Config, Direction, MacroStep, Mapping, ProgramButton, ProgramStep, Stick, Transport,
+    VelocityMode,
+};
+use crate::{
+    abs_float_to_midi, abs_float_to_u14, apply_deadzone, centered_around, centered_float_to_midi,
+    centered_float_to_u14, clamped_u14, clamped_u7, describe_message, is_command_cc, nrpn_messages,
+    rescale, rescale_u14, split_u14, sysex_message, transpose, CC_CENTER,
+};
+use gilrs::{Axis, Button, Event, EventType, GamepadId, Gilrs};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::time::{Duration, Instant, SystemTime};
+use wmidi::{Channel, ControlFunction, ControlValue, MidiMessage, Note, U14, U7};
+
+/// With `release_velocity_from_speed`, letting go of an analog button from
+/// full pressure this fast or faster gives a release velocity of 127.
+const RELEASE_FULL_SPEED: Duration = Duration::from_millis(50);
+
+/// A button on a particular gamepad.
+type PadButton = (GamepadId, Button);
+
+/// A stick on a particular gamepad.
+type PadStick = (GamepadId, Stick);
+
+/// Places the `SystemTime` gilrs stamps each event with on the `Instant`
+/// timeline, so what an event causes is timed by when it happened rather
+/// than by when it was read. The first event is taken to have happened as
+/// it's read and the rest follow on from it, though never before the event
+/// ahead of them or after they're read, whatever the system clock does
+/// meanwhile.
+#[derive(Debug, Default)]
+pub struct EventClock {
+    first: Option<(SystemTime, Instant)>,
+    last: Option<Instant>,
+}
+
+impl EventClock {
+    /// When the event stamped `time`, read at `now`, happened.
+    pub fn at(&mut self, time: SystemTime, now: Instant) -> Instant {
+        let (first_time, first_at) = *self.first.get_or_insert((time, now));
+        let at = match time.duration_since(first_time) {
+            Ok(since) => first_at + since,
+            Err(_) => first_at,
+        };
+        let at = at.min(now).max(self.last.unwrap_or(first_at));
+        self.last = Some(at);
+        at
+    }
+}
+
+/// What we've sent so far, for the mappings that depend on more than the
+/// current event.
+#[derive(Debug, Default)]
+pub struct State {
+    last_cc: HashMap<(Channel, ControlFunction), ControlValue>,
+    last_bend: HashMap<Channel, U14>,
+    last_nrpn: HashMap<(Channel, u16), U14>,
+    channels: HashMap<GamepadId, Channel>,
+    /// Notes started by a button press, so the release (or a disconnect)
+    /// stops exactly what was started.
+    held: HashMap<(GamepadId, Button), (Channel, Vec<Note>)>,
+    /// The note each `axis_notes` axis is currently sounding.
+    axis_held: HashMap<(GamepadId, Axis), (Channel, Note)>,
+    /// The note each `raw_button_notes` button is sounding. Every one of
+    /// them is `Button::Unknown`, so they go by code instead of in `held`.
+    raw_held: HashMap<(GamepadId, u32), (Channel, Note)>,
+    /// The note each `combo_notes` combo is sounding, by its index.
+    combo_held: HashMap<(GamepadId, usize), (Channel, Note)>,
+    /// The note each `trigger_note_threshold` button is sounding.
+    trigger_held: HashMap<PadButton, (Channel, Note)>,
+    used_channels: HashSet<Channel>,
+    /// Presses waiting on the position gilrs reports just after them.
+    pending_press: HashMap<(GamepadId, Button), (Channel, Vec<Note>)>,
+    last_press: HashMap<(GamepadId, Button), SystemTime>,
+    /// For `release_velocity_from_speed`, where and when each analog button
+    /// started falling, how many steps it's taken since, and where it is.
+    falls: HashMap<PadButton, Fall>,
+    /// Whether `invert_toggle_button` has the axes flipped.
+    axes_inverted: bool,
+    /// Semitones added to `keys` notes by the octave buttons.
+    octave_shift: i16,
+    /// The last program and bank sent on each channel by the program buttons.
+    program: HashMap<Channel, U7>,
+    bank: HashMap<Channel, u16>,
+    /// Channels the sustain button has the pedal down on.
+    sustain: HashSet<Channel>,
+    /// Gamepads holding the shift button.
+    shifted: HashSet<GamepadId>,
+    /// Index of the active profile, 0 being the top level of the config.
+    profile: usize,
+    /// The names of the gamepads, and the profiles of those that have their
+    /// own in `gamepad_profiles`.
+    names: HashMap<GamepadId, String>,
+    pad_profiles: HashMap<GamepadId, usize>,
+    /// When each held `note_repeat_buttons` button next retriggers, and at
+    /// what velocity.
+    repeats: HashMap<PadButton, (Instant, U7)>,
+    /// `strum_buttons` chords still starting, their remaining notes started
+    /// by `tick`.
+    strums: HashMap<PadButton, Strum>,
+    /// Where `strum_axis` is on each gamepad.
+    strum_axis_pos: HashMap<GamepadId, f32>,
+    /// Where every analog button and axis was last reported, for
+    /// `modifier_notes`.
+    button_pos: HashMap<PadButton, f32>,
+    axis_pos: HashMap<(GamepadId, Axis), f32>,
+    /// Held `arp_buttons`, played by `tick`.
+    arps: HashMap<(GamepadId, Button), Arp>,
+    /// The clock tempo, from `clock_bpm` or tapped in.
+    clock_bpm: Option<f32>,
+    next_clock: Option<Instant>,
+    clock_running: bool,
+    taps: Vec<Instant>,
+    /// For `channel_rotation`, where the next NoteOn goes in the list, and
+    /// the channels the notes mapped to each channel and note were moved to,
+    /// oldest first.
+    next_rotation: usize,
+    rotated: HashMap<(Channel, Note), VecDeque<Channel>>,
+    /// For `mpe`, the notes sounding on member channels, oldest first: the
+    /// member channel, and the channel and note the note was mapped to.
+    mpe_voices: VecDeque<(Channel, (Channel, Note))>,
+    /// Notes sounding on each channel, oldest first, for `max_voices`.
+    voices: HashMap<Channel, VecDeque<Note>>,
+    /// With `mono`, the buttons down on each channel in the order they were
+    /// pressed, and their velocities. Only the last one is sounding; the
+    /// rest wait in `held`.
+    mono_stack: HashMap<Channel, Vec<(PadButton, U7)>>,
+    /// When each `drum_buttons` note is due to stop.
+    drum_offs: HashMap<(Channel, Note), Instant>,
+    /// For `cc_rate_limit`: when each CC last went out, and the latest value
+    /// held back on those that have to wait.
+    cc_sent_at: HashMap<(Channel, ControlFunction), Instant>,
+    cc_held_back: HashMap<(Channel, ControlFunction), ControlValue>,
+    /// The same for `song_position_rate`, along with the last position sent.
+    spp_sent_at: Option<Instant>,
+    spp_held_back: Option<U14>,
+    last_spp: Option<U14>,
+    /// Where each `axis_smoothing` axis's output is and where it's heading.
+    smoothing: HashMap<(GamepadId, Axis), Smoothed>,
+    /// The latest position of each `stick_zones` stick, since gilrs reports
+    /// its axes separately.
+    sticks: HashMap<PadStick, (f32, f32)>,
+    /// The zone each of those sticks is in, and the note it's sounding.
+    stick_zone: HashMap<PadStick, (Direction, Option<(Channel, Note)>)>,
+    /// `macros` and `on_connect` steps yet to go out, in the order they're
+    /// due.
+    macro_queue: Vec<QueuedStep>,
+    /// Buttons down on each gamepad, for `profile_combo`.
+    pressed: HashSet<PadButton>,
+    /// For `velocity_humanize`, seeded on first use from `humanize_seed`.
+    rng: Option<SmallRng>,
+    /// `raw_button_bytes` waiting for `take_raw`, which can't go out as a
+    /// `MidiMessage`.
+    raw_out: Vec<Vec<u8>>,
+    /// For `button_debounce_ms`: the last press or release let through for
+    /// each button, and when.
+    edges: HashMap<PadButton, (Instant, bool)>,
+}
+
+#[derive(Debug)]
+struct QueuedStep {
+    at: Instant,
+    /// `None` for `on_connect`.
+    button: Option<PadButton>,
+    channel: Channel,
+    step: MacroStep,
+}
+
+#[derive(Debug)]
+struct Smoothed {
+    current: f32,
+    target: f32,
+    last: Instant,
+}
+
+#[derive(Debug)]
+struct Arp {
+    channel: Channel,
+    notes: Vec<Note>,
+    velocity: U7,
+    step: usize,
+    next: Instant,
+    playing: Option<Note>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Fall {
+    from: f32,
+    at: SystemTime,
+    steps: u32,
+    pos: f32,
+    last: SystemTime,
+}
+
+#[derive(Debug)]
+struct Strum {
+    channel: Channel,
+    /// Notes yet to start, in the order they're played.
+    notes: VecDeque<Note>,
+    velocity: U7,
+    next: Instant,
+}
+
+impl State {
+    pub fn new(cfg: &Config) -> State {
+        State {
+            clock_bpm: cfg.clock_bpm,
+            ..State::default()
+        }
+    }
+
+    /// The NoteOn velocity for a press of `btn` at `time`, per `velocity_mode`.
+    fn press_velocity(
+        &mut self,
+        cfg: &Config,
+        id: GamepadId,
+        btn: Button,
+        time: SystemTime,
+        pressure: Option<f32>,
+    ) -> U7 {
+        let last_press = self.last_press.insert((id, btn), time);
+        let velocity = cfg.button_velocity(btn);
+        let v = match cfg.velocity_mode {
+            VelocityMode::Fixed => return velocity,
+            VelocityMode::FromPressure => match pressure {
+                Some(pos) => u8::from(abs_float_to_midi(pos)),
+                None => return velocity,
+            },
+            VelocityMode::FromSpeed => {
+                let window = Duration::from_millis(cfg.velocity_speed_window_ms);
+                let since = last_press.and_then(|t| time.duration_since(t).ok());
+                match since {
+                    Some(since) if since < window => {
+                        let fast = 1.0 - since.as_secs_f32() / window.as_secs_f32();
+                        let base = u8::from(velocity) as f32;
+                        (base + (127.0 - base) * fast).round() as u8
+                    }
+                    _ => return velocity,
+                }
+            }
+        };
+        // Velocity 0 would read as a NoteOff.
+        U7::from_u8_lossy(v.max(1))
+    }
+
+    /// Follows the position of an analog button for `release_velocity`.
+    fn track_fall(&mut self, key: PadButton, pos: f32, time: SystemTime) {
+        let fall = self.falls.entry(key).or_insert(Fall {
+            from: pos,
+            at: time,
+            steps: 0,
+            pos,
+            last: time,
+        });
+        if pos < fall.pos {
+            fall.steps += 1;
+        } else {
+            // Held or pressed harder, any fall starts from here.
+            fall.from = pos;
+            fall.at = time;
+            fall.steps = 0;
+        }
+        fall.pos = pos;
+        fall.last = time;
+    }
+
+    /// The NoteOff velocity for a release of `key`, per
+    /// `release_velocity_from_speed`.
+    fn release_velocity(&mut self, cfg: &Config, key: PadButton) -> U7 {
+        let fall = match self.falls.remove(&key) {
+            // gilrs reports the release ahead of the position that
+            // triggered it, so a digital button, which drops from 1 to 0 in
+            // one go, hasn't moved at all yet.
+            Some(fall) if cfg.release_velocity_from_speed && fall.steps > 0 => fall,
+            _ => return cfg.note_off_velocity,
+        };
+        let took = fall.last.duration_since(fall.at).unwrap_or_default();
+        let speed = (fall.from - fall.pos) / took.as_secs_f32().max(1e-3);
+        clamped_u7(127.0 * speed * RELEASE_FULL_SPEED.as_secs_f32())
+    }
+
+    /// Forgets every note `id` is holding and returns them for NoteOffs.
+    fn release_gamepad(&mut self, id: GamepadId) -> Vec<(Channel, Note)> {
+        let keys: Vec<_> = self
+            .held
+            .keys()
+            .chain(self.arps.keys())
+            .filter(|k| k.0 == id)
+            .copied()
+            .collect();
+        let axes: Vec<_> = self
+            .axis_held
+            .keys()
+            .filter(|k| k.0 == id)
+            .copied()
+            .collect();
+        let mut released: Vec<_> = keys
+            .iter()
+            .flat_map(|k| self.release_button(k.0, k.1))
+            .collect();
+        released.extend(axes.iter().filter_map(|k| self.axis_held.remove(k)));
+        let triggers: Vec<_> = self
+            .trigger_held
+            .keys()
+            .filter(|k| k.0 == id)
+            .copied()
+            .collect();
+        released.extend(triggers.iter().filter_map(|k| self.trigger_held.remove(k)));
+        let raw: Vec<_> = self
+            .raw_held
+            .keys()
+            .filter(|k| k.0 == id)
+            .copied()
+            .collect();
+        released.extend(raw.iter().filter_map(|k| self.raw_held.remove(k)));
+        let combos: Vec<_> = self
+            .combo_held
+            .keys()
+            .filter(|k| k.0 == id)
+            .copied()
+            .collect();
+        released.extend(combos.iter().filter_map(|k| self.combo_held.remove(k)));
+        self.sticks.retain(|(pad, _), _| *pad != id);
+        self.edges.retain(|(pad, _), _| *pad != id);
+        self.pressed.retain(|(pad, _)| *pad != id);
+        let sticks: Vec<_> = self
+            .stick_zone
+            .keys()
+            .filter(|k| k.0 == id)
+            .copied()
+            .collect();
+        released.extend(
+            sticks
+                .iter()
+                .filter_map(|k| self.stick_zone.remove(k).and_then(|(_, held)| held)),
+        );
+        for stack in self.mono_stack.values_mut() {
+            stack.retain(|((pad, _), _)| *pad != id);
+        }
+        // A press still waiting on its position would start once the pad is
+        // back.
+        self.pending_press.retain(|(pad, _), _| *pad != id);
+        released
+    }
+
+    /// Starts the notes for a press, all at once or as an arpeggio.
+    fn start_notes(
+        &mut self,
+        cfg: &Config,
+        (id, btn): (GamepadId, Button),
+        (channel, notes): (Channel, Vec<Note>),
+        velocity: U7,
+        now: Instant,
+        out: &mut Vec<MidiMessage<'static>>,
+    ) {
+        if cfg.drum_buttons.contains(&btn) {
+            for note in notes {
+                self.hit(cfg, (channel, note), velocity, now, out);
+            }
+            return;
+        }
+        if cfg.arp_buttons.contains(&btn) && !notes.is_empty() {
+            let arp = Arp {
+                channel,
+                notes,
+                velocity,
+                step: 0,
+                next: now,
+                playing: None,
+            };
+            self.arps.insert((id, btn), arp);
+            return;
+        }
+        if cfg.mono {
+            let held = &self.held;
+            let stack = self.mono_stack.entry(channel).or_default();
+            let previous = stack
+                .last()
+                .and_then(|(k, _)| held.get(k))
+                .map(|(_, notes)| notes.clone())
+                .unwrap_or_default();
+            stack.push(((id, btn), velocity));
+            switch_notes(cfg, channel, &previous, &notes, velocity, out);
+        } else if cfg.strum_buttons.contains(&btn) && notes.len() > 1 {
+            let mut notes: VecDeque<_> = notes.into_iter().collect();
+            let down = cfg
+                .strum_down_button
+                .is_some_and(|b| self.pressed.contains(&(id, b)))
+                || self.strum_axis_pos.get(&id).is_some_and(|pos| *pos < 0.0);
+            if down {
+                notes.make_contiguous().reverse();
+            }
+            // Only what's started goes in `held`, so a release mid-strum
+            // stops just those and `release_button` drops the rest.
+            let first = notes.pop_front().into_iter().collect::<Vec<_>>();
+            for note in first.iter() {
+                out.push(MidiMessage::NoteOn(channel, *note, velocity));
+            }
+            self.held.insert((id, btn), (channel, first));
+            let strum = Strum {
+                channel,
+                notes,
+                velocity,
+                next: now + Duration::from_millis(cfg.strum_ms),
+            };
+            self.strums.insert((id, btn), strum);
+            return;
+        } else {
+            for note in notes.iter() {
+                out.push(MidiMessage::NoteOn(channel, *note, velocity));
+            }
+            if let Some(ms) = cfg.note_repeat_buttons.get(&btn) {
+                let next = now + Duration::from_millis(*ms);
+                self.repeats.insert((id, btn), (next, velocity));
+            }
+        }
+        self.held.insert((id, btn), (channel, notes));
+    }
+
+    /// Stops what `btn` started. In `mono` that can mean going back to the
+    /// button pressed before it.
+    fn stop_button(
+        &mut self,
+        cfg: &Config,
+        (id, btn): (GamepadId, Button),
+        velocity: U7,
+        out: &mut Vec<MidiMessage<'static>>,
+    ) {
+        let channel = self.held.get(&(id, btn)).map(|(ch, _)| *ch);
+        let stack = channel.and_then(|ch| self.mono_stack.get_mut(&ch));
+        let stack = match stack {
+            Some(stack) if cfg.mono => stack,
+            _ => {
+                for (ch, note) in self.release_button(id, btn) {
+                    out.push(MidiMessage::NoteOff(ch, note, velocity));
+                }
+                return;
+            }
+        };
+        let was_top = stack.last().map(|(k, _)| *k) == Some((id, btn));
+        stack.retain(|(k, _)| *k != (id, btn));
+        let next = stack.last().copied();
+        let (channel, notes) = match self.held.remove(&(id, btn)) {
+            Some(held) => held,
+            None => return,
+        };
+        // Buttons below the top of the stack aren't sounding, there's
+        // nothing to stop.
+        if !was_top {
+            return;
+        }
+        match next.and_then(|(k, velocity)| Some((self.held.get(&k)?, velocity))) {
+            Some(((_, next_notes), velocity)) => {
+                switch_notes(cfg, channel, &notes, next_notes, velocity, out)
+            }
+            None => {
+                for note in notes {
+                    out.push(MidiMessage::NoteOff(channel, note, velocity));
+                }
+            }
+        }
+    }
+
+    /// Whether `btn` has notes playing (or an arpeggio running).
+    fn is_sounding(&self, id: GamepadId, btn: Button) -> bool {
+        self.held.contains_key(&(id, btn)) || self.arps.contains_key(&(id, btn))
+    }
+
+    /// Forgets what `btn` started and returns the notes to stop.
+    fn release_button(&mut self, id: GamepadId, btn: Button) -> Vec<(Channel, Note)> {
+        let mut released = Vec::new();
+        self.strums.remove(&(id, btn));
+        self.repeats.remove(&(id, btn));
+        if let Some((ch, notes)) = self.held.remove(&(id, btn)) {
+            released.extend(notes.into_iter().map(|note| (ch, note)));
+        }
+        if let Some(Arp {
+            channel,
+            playing: Some(note),
+            ..
+        }) = self.arps.remove(&(id, btn))
+        {
+            released.push((channel, note));
+        }
+        released
+    }
+
+    /// Runs everything that happens on a timer rather than in response to an
+    /// event. Called every time round the main loop.
+    pub fn tick(&mut self, cfg: &Config, now: Instant, out: &mut Vec<MidiMessage<'static>>) {
+        let mut eased = Vec::new();
+        for ((id, ax), s) in self.smoothing.iter_mut() {
+            let dt = now.duration_since(s.last).as_secs_f32() * 1000.0;
+            s.last = now;
+            if s.current == s.target {
+                continue;
+            }
+            let tau = cfg.axis_smoothing.get(ax).copied().unwrap_or(0.0);
+            s.current += (s.target - s.current) * (1.0 - (-dt / tau).exp());
+            // The exponential never quite gets there, land on the target once
+            // the rest is too small to show up in the output (or straight
+            // away with a time constant of 0, which can come out as NaN).
+            if s.current.is_nan() || (s.target - s.current).abs() <= 1e-4 {
+                s.current = s.target;
+            }
+            eased.push((*id, *ax, s.current));
+        }
+        for (id, ax, pos) in eased {
+            if let Some(channel) = self.channels.get(&id).copied() {
+                let channel = cfg.axis_channel(ax, channel);
+                self.axis_controls(cfg, (id, ax), channel, pos, now, out);
+            }
+        }
+        if !self.cc_held_back.is_empty() {
+            let interval = self.cc_interval(cfg);
+            let sent_at = &self.cc_sent_at;
+            let mut due: Vec<_> = self
+                .cc_held_back
+                .iter()
+                .filter(|(k, _)| {
+                    sent_at
+                        .get(k)
+                        .is_none_or(|t| now.duration_since(*t) >= interval)
+                })
+                .map(|(k, v)| (*k, *v))
+                .collect();
+            // MSBs ahead of their LSBs, a new MSB resets the LSB.
+            due.sort_by_key(|((ch, cc), _)| (ch.index(), u8::from(cc.0)));
+            for ((ch, cc), value) in due {
+                out.push(MidiMessage::ControlChange(ch, cc, value));
+            }
+        }
+        if let Some(position) = self.spp_held_back {
+            self.song_position(cfg, position, now, out);
+        }
+        let due = self.macro_queue.partition_point(|q| q.at <= now);
+        for q in self.macro_queue.drain(..due).collect::<Vec<_>>() {
+            let step = q.step;
+            let channel = step.channel.unwrap_or(q.channel);
+            let sent = out.len();
+            if let Some(note) = step.note {
+                let velocity = match q.button {
+                    Some((_, btn)) => cfg.button_velocity(btn),
+                    None => cfg.velocity,
+                };
+                self.hit(cfg, (channel, note), velocity, now, out);
+            }
+            if let Some((cc, value)) = step.cc {
+                out.push(MidiMessage::ControlChange(
+                    channel,
+                    ControlFunction(cc),
+                    value,
+                ));
+            }
+            if let Some(program) = step.program {
+                out.push(MidiMessage::ProgramChange(channel, program));
+            }
+            if let Some(bend) = step.bend.and_then(|b| U14::try_from(b).ok()) {
+                out.push(MidiMessage::PitchBendChange(channel, bend));
+            }
+            if let Some(bytes) = step.sysex {
+                out.push(sysex_message(&bytes));
+            }
+            if q.button.is_none() {
+                for mm in out[sent..].iter() {
+                    log::info!("On connect: {}", describe_message(mm));
+                }
+            }
+        }
+        let note_off_velocity = cfg.note_off_velocity;
+        self.drum_offs.retain(|(ch, note), off_at| {
+            if now < *off_at {
+                return true;
+            }
+            out.push(MidiMessage::NoteOff(*ch, *note, note_off_velocity));
+            false
+        });
+        for (key, (next, velocity)) in self.repeats.iter_mut() {
+            let every = match cfg.note_repeat_buttons.get(&key.1) {
+                Some(ms) => Duration::from_millis(*ms),
+                None => continue,
+            };
+            if now < *next {
+                continue;
+            }
+            if let Some((ch, notes)) = self.held.get(key) {
+                for note in notes.iter() {
+                    out.push(MidiMessage::NoteOff(*ch, *note, cfg.note_off_velocity));
+                    out.push(MidiMessage::NoteOn(*ch, *note, *velocity));
+                }
+            }
+            // Skip any repeats missed rather than fire them all at once.
+            while *next <= now {
+                *next += every;
+            }
+        }
+        let gap = Duration::from_millis(cfg.strum_ms);
+        for (key, strum) in self.strums.iter_mut() {
+            while now >= strum.next {
+                let note = match strum.notes.pop_front() {
+                    Some(note) => note,
+                    None => break,
+                };
+                out.push(MidiMessage::NoteOn(strum.channel, note, strum.velocity));
+                if let Some((_, notes)) = self.held.get_mut(key) {
+                    notes.push(note);
+                }
+                strum.next += gap;
+            }
+        }
+        self.strums.retain(|_, strum| !strum.notes.is_empty());
+        let step = Duration::from_secs_f32(60.0 / (cfg.arp_bpm * cfg.arp_division as f32));
+        for arp in self.arps.values_mut() {
+            if now < arp.next {
+                continue;
+            }
+            if let Some(note) = arp.playing.take() {
+                out.push(MidiMessage::NoteOff(
+                    arp.channel,
+                    note,
+                    cfg.note_off_velocity,
+                ));
+            }
+            let note = arp.notes[cfg.arp_pattern.index(arp.notes.len(), arp.step)];
+            out.push(MidiMessage::NoteOn(arp.channel, note, arp.velocity));
+            arp.playing = Some(note);
+            arp.step += 1;
+            // Keep to the beat, unless we've fallen so far behind that
+            // catching up would mean a burst of notes.
+            arp.next = (arp.next + step).max(now);
+        }
+        if let Some(bpm) = self.clock_bpm {
+            let period = Duration::from_secs_f32(60.0 / (bpm * 24.0));
+            let next = self.next_clock.get_or_insert(now);
+            if now >= *next {
+                out.push(MidiMessage::TimingClock);
+                *next = (*next + period).max(now);
+            }
+        }
+    }
+
+    /// Notes a press of the tap tempo button, and sets the clock tempo from
+    /// the average gap between the last few presses.
+    fn tap(&mut self, now: Instant) {
+        // Taps further apart than this start a new count.
+        const MAX_GAP: Duration = Duration::from_secs(2);
+        if let Some(last) = self.taps.last() {
+            if now.duration_since(*last) > MAX_GAP {
+                self.taps.clear();
+            }
+        }
+        self.taps.push(now);
+        if self.taps.len() > 4 {
+            self.taps.remove(0);
+        }
+        if let (Some(first), Some(last)) = (self.taps.first(), self.taps.last()) {
+            let gaps = self.taps.len() as f32 - 1.0;
+            if gaps > 0.0 {
+                let bpm = 60.0 * gaps / last.duration_since(*first).as_secs_f32();
+                self.clock_bpm = Some(bpm);
+                log::info!("Tempo: {:.1} bpm", bpm);
+            }
+        }
+    }
+
+    /// Sends `value` to NRPN `param`, unless that's what it already has.
+    fn nrpn(
+        &mut self,
+        channel: Channel,
+        param: u16,
+        value: U14,
+        out: &mut Vec<MidiMessage<'static>>,
+    ) {
+        if self.last_nrpn.insert((channel, param), value) != Some(value) {
+            out.extend(nrpn_messages(channel, param, value));
+        }
+    }
+
+    /// The CCs and pitch bend for an axis at `pos`, after its curve.
+    fn axis_controls(
+        &mut self,
+        cfg: &Config,
+        (id, ax): (GamepadId, Axis),
+        channel: Channel,
+        pos: f32,
+        now: Instant,
+        out: &mut Vec<MidiMessage<'static>>,
+    ) {
+        if let Some(cc) = self.mapping(cfg, id).axis_ccs.get(&ax) {
+            let range = cfg.axis_range.get(&ax).copied();
+            let center = cfg.axis_center.get(&ax).copied().unwrap_or(CC_CENTER);
+            if cfg.hires_axis_ccs.contains(&ax) {
+                let center = f32::from(u8::from(center)) * 128.0;
+                let mut value = clamped_u14(center + pos.clamp(-1.0, 1.0) * 8192.0);
+                if let Some((min, max)) = range {
+                    // The same window, with the LSB running over the top
+                    // value so that `max` itself is reachable.
+                    let (min, max) = (u16::from(u8::from(min)), u16::from(u8::from(max)));
+                    value = rescale_u14(value, min << 7, max << 7 | 0x7F);
+                }
+                let (msb, lsb) = split_u14(value);
+                let lsb_cc = U7::from_u8_lossy(u8::from(*cc) + 32);
+                out.push(MidiMessage::ControlChange(
+                    channel,
+                    ControlFunction::from(*cc),
+                    msb,
+                ));
+                out.push(MidiMessage::ControlChange(
+                    channel,
+                    ControlFunction::from(lsb_cc),
+                    lsb,
+                ));
+            } else {
+                out.push(MidiMessage::ControlChange(
+                    channel,
+                    ControlFunction::from(*cc),
+                    match range {
+                        Some((min, max)) => rescale(centered_around(pos, center), min, max),
+                        None => centered_around(pos, center),
+                    },
+                ));
+            }
+        }
+        if let Some((a, b)) = cfg.axis_crossfade.get(&ax) {
+            out.push(MidiMessage::ControlChange(
+                channel,
+                ControlFunction::from(*a),
+                centered_float_to_midi(-pos),
+            ));
+            out.push(MidiMessage::ControlChange(
+                channel,
+                ControlFunction::from(*b),
+                centered_float_to_midi(pos),
+            ));
+        }
+        if cfg.axis_pitchbend.contains(&ax) {
+            out.push(MidiMessage::PitchBendChange(
+                channel,
+                centered_float_to_u14(pos * cfg.pitchbend_range),
+            ));
+        }
+        if let Some(param) = cfg.axis_nrpn.get(&ax) {
+            self.nrpn(channel, *param, centered_float_to_u14(pos), out);
+        }
+        if Some(ax) == cfg.song_position_axis {
+            self.song_position(cfg, centered_float_to_u14(pos), now, out);
+        }
+    }
+
+    /// Sends `position` as a Song Position Pointer, or holds it back for
+    /// `tick` if one went out too recently.
+    fn song_position(
+        &mut self,
+        cfg: &Config,
+        position: U14,
+        now: Instant,
+        out: &mut Vec<MidiMessage<'static>>,
+    ) {
+        let interval = Duration::from_secs(1) / cfg.song_position_rate.max(1);
+        if self
+            .spp_sent_at
+            .is_some_and(|t| now.duration_since(t) < interval)
+        {
+            self.spp_held_back = Some(position);
+            return;
+        }
+        self.spp_held_back = None;
+        if self.last_spp.replace(position) != Some(position) {
+            self.spp_sent_at = Some(now);
+            out.push(MidiMessage::SongPositionPointer(position));
+        }
+    }
+
+    /// Whether a press (or release) of `button` at `now` should count, or is
+    /// a bounce per `button_debounce_ms`.
+    pub fn debounce(
+        &mut self,
+        cfg: &Config,
+        button: PadButton,
+        pressed: bool,
+        now: Instant,
+    ) -> bool {
+        if cfg.button_debounce_ms == 0 {
+            return true;
+        }
+        let window = Duration::from_millis(cfg.button_debounce_ms);
+        match self.edges.get(&button) {
+            // A repeat of the last edge means the one between was dropped.
+            Some((at, was)) if *was == pressed || now.duration_since(*at) < window => false,
+            _ => {
+                self.edges.insert(button, (now, pressed));
+                true
+            }
+        }
+    }
+
+    /// Buttons that ended up the other way from their last edge let
+    /// through, once the debounce window is over: a bounce swallowed
+    /// the edge they settled on, which would otherwise leave notes stuck.
+    pub fn unsettled(&self, cfg: &Config, gilrs: &Gilrs, now: Instant) -> Vec<Event> {
+        let window = Duration::from_millis(cfg.button_debounce_ms);
+        self.edges
+            .iter()
+            .filter(|(_, (at, _))| now.duration_since(*at) >= window)
+            .filter_map(|((id, btn), (_, pressed))| {
+                let gamepad = gilrs.connected_gamepad(*id)?;
+                let code = gamepad.button_code(*btn)?;
+                if gamepad.is_pressed(*btn) == *pressed {
+                    return None;
+                }
+                let events = if *pressed {
+                    vec![EventType::ButtonReleased(*btn, code)]
+                } else {
+                    // Followed by its position, like gilrs does, for
+                    // `FromPressure` velocity.
+                    let pos = gamepad.button_data(*btn).map_or(1.0, |data| data.value());
+                    vec![
+                        EventType::ButtonPressed(*btn, code),
+                        EventType::ButtonChanged(*btn, pos, code),
+                    ]
+                };
+                Some(events.into_iter().map(move |event| Event::new(*id, event)))
+            })
+            .flatten()
+            .collect()
+    }
+
+    fn mapping<'a>(&self, cfg: &'a Config, id: GamepadId) -> Mapping<'a> {
+        cfg.mapping(self.profile_of(id), self.shifted.contains(&id))
+    }
+
+    /// What a press of `btn` plays: its `modifier_notes` note if it has
+    /// one, otherwise what the mapping has for it.
+    fn press_notes(&self, cfg: &Config, id: GamepadId, btn: Button) -> Option<Vec<Note>> {
+        let m = match cfg.modifier_notes.get(&btn) {
+            Some(m) => m,
+            None => return self.mapping(cfg, id).notes(btn),
+        };
+        let depth = match (m.button, m.axis) {
+            (Some(b), _) => self.button_pos.get(&(id, b)).copied().unwrap_or(0.0),
+            (_, Some(ax)) => {
+                let pos = self.axis_pos.get(&(id, ax)).copied().unwrap_or(0.0);
+                (pos + 1.0) / 2.0
+            }
+            (None, None) => 0.0,
+        };
+        m.pick(depth).map(|note| vec![note])
+    }
+
+    /// Forgets every held note on every gamepad.
+    fn release_all(&mut self) -> Vec<(Channel, Note)> {
+        let mut released: Vec<_> = self
+            .held
+            .drain()
+            .flat_map(|(_, (ch, notes))| notes.into_iter().map(move |note| (ch, note)))
+            .collect();
+        released.extend(
+            self.arps
+                .drain()
+                .filter_map(|(_, arp)| Some((arp.channel, arp.playing?))),
+        );
+        released.extend(self.axis_held.drain().map(|(_, held)| held));
+        released.extend(self.trigger_held.drain().map(|(_, held)| held));
+        released.extend(self.raw_held.drain().map(|(_, held)| held));
+        released.extend(self.combo_held.drain().map(|(_, held)| held));
+        released.extend(self.drum_offs.drain().map(|(held, _)| held));
+        released.extend(self.stick_zone.drain().filter_map(|(_, (_, held))| held));
+        self.mono_stack.clear();
+        self.strums.clear();
+        self.repeats.clear();
+        released
+    }
+
+    /// Starts and stops `combo_notes` after `btn` on `id` has been pressed
+    /// or released.
+    fn update_combos(
+        &mut self,
+        cfg: &Config,
+        (id, btn): PadButton,
+        channel: Channel,
+        out: &mut Vec<MidiMessage<'static>>,
+    ) {
+        let down: Vec<bool> = cfg
+            .combo_notes
+            .iter()
+            .map(|c| c.buttons.iter().all(|b| self.pressed.contains(&(id, *b))))
+            .collect();
+        for (i, _) in down.iter().enumerate().filter(|(_, down)| !**down) {
+            if let Some((ch, note)) = self.combo_held.remove(&(id, i)) {
+                out.push(MidiMessage::NoteOff(ch, note, cfg.note_off_velocity));
+            }
+        }
+        if !self.pressed.contains(&(id, btn)) {
+            return;
+        }
+        let completed: Vec<usize> = (0..down.len())
+            .filter(|i| down[*i] && cfg.combo_notes[*i].buttons.contains(&btn))
+            .collect();
+        for i in completed.iter().copied() {
+            let combo = &cfg.combo_notes[i];
+            if completed.iter().any(|j| combo.within(&cfg.combo_notes[*j])) {
+                continue;
+            }
+            let smaller: Vec<_> = self
+                .combo_held
+                .keys()
+                .filter(|k| k.0 == id && cfg.combo_notes[k.1].within(combo))
+                .copied()
+                .collect();
+            for k in smaller {
+                if let Some((ch, note)) = self.combo_held.remove(&k) {
+                    out.push(MidiMessage::NoteOff(ch, note, cfg.note_off_velocity));
+                }
+            }
+            let shift = cfg.transpose as i16 + self.octave_shift;
+            let note = cfg.quantize(transpose(combo.note, shift));
+            out.push(MidiMessage::NoteOn(channel, note, cfg.velocity));
+            self.combo_held.insert((id, i), (channel, note));
+        }
+    }
+
+    /// Plays `note` for `gate_ms` from `now`.
+    fn hit(
+        &mut self,
+        cfg: &Config,
+        (channel, note): (Channel, Note),
+        velocity: U7,
+        now: Instant,
+        out: &mut Vec<MidiMessage<'static>>,
+    ) {
+        let off_at = now + Duration::from_millis(cfg.gate_ms);
+        // Stop the previous hit now rather than have its NoteOff cut this
+        // one short.
+        if self.drum_offs.insert((channel, note), off_at).is_some() {
+            out.push(MidiMessage::NoteOff(channel, note, cfg.note_off_velocity));
+        }
+        out.push(MidiMessage::NoteOn(channel, note, velocity));
+    }
+
+    /// The `raw_button_bytes` pressed since the last call, to send after
+    /// what `map_event` returned.
+    pub fn take_raw(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.raw_out)
+    }
+
+    /// Queues up `on_connect` for `tick` to send, with its delays, on
+    /// `output_midi_channel`.
+    pub fn on_connect(&mut self, cfg: &Config, now: Instant) {
+        self.start_macro(None, cfg.output_midi_channel, &cfg.on_connect, now);
+    }
+
+    /// Queues up the steps of `button`'s macro, dropping whatever's left of
+    /// the last time it was pressed.
+    fn start_macro(
+        &mut self,
+        button: Option<PadButton>,
+        channel: Channel,
+        steps: &[MacroStep],
+        now: Instant,
+    ) {
+        self.macro_queue.retain(|q| q.button != button);
+        let mut at = now;
+        for step in steps {
+            at += Duration::from_millis(step.delay_ms);
+            // After anything else due at the same time, so steps that
+            // share a deadline keep their order.
+            let i = self.macro_queue.partition_point(|q| q.at <= at);
+            self.macro_queue.insert(
+                i,
+                QueuedStep {
+                    at,
+                    button,
+                    channel,
+                    step: step.clone(),
+                },
+            );
+        }
+    }
+
+    /// A raw axis position after `Config::calibrate_axis` and, while
+    /// `invert_toggle_button` has them flipped, inverted once more.
+    pub fn calibrate_axis(&self, cfg: &Config, axis: Axis, pos: f32) -> f32 {
+        let pos = cfg.calibrate_axis(axis, pos);
+        if self.axes_inverted {
+            -pos
+        } else {
+            pos
+        }
+    }
+
+    /// Moves every axis to the other side of center after the axes have been
+    /// flipped, as `invert_toggle_resend` asks.
+    fn flip_axes(&mut self, cfg: &Config, now: Instant, out: &mut Vec<MidiMessage<'static>>) {
+        let axes: Vec<_> = self.axis_pos.iter().map(|(k, pos)| (*k, *pos)).collect();
+        for ((id, ax), pos) in axes {
+            let channel = cfg.axis_channel(ax, self.channel_for(cfg, id));
+            self.move_axis(cfg, (id, ax), channel, -pos, now, out);
+        }
+    }
+
+    /// Everything an axis at calibrated position `pos` sends.
+    fn move_axis(
+        &mut self,
+        cfg: &Config,
+        (id, ax): (GamepadId, Axis),
+        channel: Channel,
+        pos: f32,
+        now: Instant,
+        out: &mut Vec<MidiMessage<'static>>,
+    ) {
+        self.axis_pos.insert((id, ax), pos);
+        // Zones have a deadzone of their own, around the
+        // center rather than on each axis.
+        self.move_stick(cfg, (id, ax), channel, pos, out);
+        let pos = cfg.shape_axis(ax, pos);
+        if Some(ax) == cfg.strum_axis {
+            self.strum_axis_pos.insert(id, pos);
+        }
+        if cfg.axis_smoothing.contains_key(&ax) {
+            // `tick` takes it from here.
+            let smoothed = self.smoothing.entry((id, ax)).or_insert(Smoothed {
+                current: 0.0,
+                target: 0.0,
+                last: now,
+            });
+            smoothed.target = pos;
+        } else {
+            self.axis_controls(cfg, (id, ax), channel, pos, now, out);
+        }
+        if let Some((low, high)) = cfg.axis_notes.get(&ax) {
+            let note = if pos == 0.0 {
+                None
+            } else {
+                let low = transpose(*low, cfg.transpose as i16) as f32;
+                let high = transpose(*high, cfg.transpose as i16) as f32;
+                let n = low + (pos + 1.0) / 2.0 * (high - low);
+                Some(cfg.quantize(n.round() as u8))
+            };
+            let playing = self.axis_held.get(&(id, ax)).copied();
+            if playing.map(|(_, n)| n) != note {
+                if let Some((ch, old)) = playing {
+                    out.push(MidiMessage::NoteOff(ch, old, cfg.note_off_velocity));
+                    self.axis_held.remove(&(id, ax));
+                }
+                if let Some(note) = note {
+                    out.push(MidiMessage::NoteOn(channel, note, cfg.velocity));
+                    self.axis_held.insert((id, ax), (channel, note));
+                }
+            }
+        }
+    }
+
+    /// Moves one axis of a `stick_zones` stick, sending whatever the zone
+    /// it's moved into (if that's a different one) sends.
+    fn move_stick(
+        &mut self,
+        cfg: &Config,
+        (id, ax): (GamepadId, Axis),
+        channel: Channel,
+        pos: f32,
+        out: &mut Vec<MidiMessage<'static>>,
+    ) {
+        let (stick, is_x) = match Stick::of(ax) {
+            Some(stick) => stick,
+            None => return,
+        };
+        let zones = match cfg.stick_zones.get(&stick) {
+            Some(zones) => zones,
+            None => return,
+        };
+        let xy = self.sticks.entry((id, stick)).or_insert((0.0, 0.0));
+        if is_x {
+            xy.0 = pos;
+        } else {
+            xy.1 = pos;
+        }
+        let (x, y) = *xy;
+        let diagonals = zones.diagonals();
+        let current = self.stick_zone.get(&(id, stick)).map(|(d, _)| *d);
+        let direction = if x.hypot(y) < zones.deadzone {
+            None
+        } else {
+            // A few degrees of slack keeps a stick resting on the edge
+            // between two zones from flicking back and forth.
+            match current {
+                Some(d) if d.contains(x, y, diagonals, 0.1) => Some(d),
+                _ => Some(Direction::of(x, y, diagonals)),
+            }
+        };
+        if direction == current {
+            return;
+        }
+        if let Some((_, Some((ch, note)))) = self.stick_zone.remove(&(id, stick)) {
+            out.push(MidiMessage::NoteOff(ch, note, cfg.note_off_velocity));
+        }
+        if let Some(direction) = direction {
+            log::debug!("{} {:?} stick {:?}", id, stick, direction);
+            for (cc, value) in zones.ccs.get(&direction).into_iter().flatten() {
+                out.push(MidiMessage::ControlChange(
+                    channel,
+                    ControlFunction(*cc),
+                    *value,
+                ));
+            }
+            let note = zones
+                .notes
+                .get(&direction)
+                .map(|note| cfg.quantize(transpose(*note, cfg.transpose as i16)));
+            if let Some(note) = note {
+                out.push(MidiMessage::NoteOn(channel, note, cfg.velocity));
+            }
+            self.stick_zone
+                .insert((id, stick), (direction, note.map(|note| (channel, note))));
+        }
+    }
+
+    /// Picks the profile for gamepad `id`, called `name`, from
+    /// `gamepad_profiles` once it's connected.
+    pub fn name_gamepad(&mut self, cfg: &Config, id: GamepadId, name: &str) {
+        self.names.insert(id, name.to_string());
+        match cfg.gamepad_profile(name) {
+            Some(profile) => {
+                self.pad_profiles.insert(id, profile);
+            }
+            None => {
+                self.pad_profiles.remove(&id);
+            }
+        }
+        log::info!(
+            "Gamepad {} ({}) uses profile {}",
+            id,
+            name,
+            cfg.profile_name(self.profile_of(id))
+        );
+    }
+
+    fn profile_of(&self, id: GamepadId) -> usize {
+        self.pad_profiles.get(&id).copied().unwrap_or(self.profile)
+    }
+
+    /// The channel a gamepad sends on, assigning the next one from
+    /// `gamepad_channels` the first time we see it.
+    pub fn channel_for(&mut self, cfg: &Config, id: GamepadId) -> Channel {
+        let next = self.channels.len();
+        *self.channels.entry(id).or_insert_with(|| {
+            if cfg.gamepad_channels.is_empty() {
+                return cfg.output_midi_channel;
+            }
+            match cfg.gamepad_channels.get(next) {
+                Some(ch) => {
+                    log::info!("Gamepad {} sends on channel {}", id, ch.number());
+                    *ch
+                }
+                None => {
+                    log::warn!(
+                        "Gamepad {} has no entry in gamepad_channels, sending on channel {}",
+                        id,
+                        cfg.output_midi_channel.number()
+                    );
+                    cfg.output_midi_channel
+                }
+            }
+        })
+    }
+
+    /// Applies `max_voices` to `mm`, returning the messages to send ahead of
+    /// it, or `None` if it shouldn't be sent at all.
+    fn allocate_voice(
+        &mut self,
+        cfg: &Config,
+        mm: &MidiMessage,
+    ) -> Option<Vec<MidiMessage<'static>>> {
+        let max_voices = match cfg.max_voices {
+            Some(max_voices) => max_voices,
+            None => return Some(Vec::new()),
+        };
+        let mut stolen = Vec::new();
+        match *mm {
+            MidiMessage::NoteOn(ch, note, _) => {
+                let voices = self.voices.entry(ch).or_default();
+                voices.retain(|n| *n != note);
+                while voices.len() >= max_voices {
+                    if let Some(oldest) = voices.pop_front() {
+                        stolen.push(MidiMessage::NoteOff(ch, oldest, cfg.note_off_velocity));
+                    }
+                }
+                voices.push_back(note);
+            }
+            MidiMessage::NoteOff(ch, note, _) => {
+                let voices = self.voices.entry(ch).or_default();
+                let before = voices.len();
+                voices.retain(|n| *n != note);
+                // The note was already stolen; stopping it again could cut
+                // off a later press of the same note.
+                if voices.len() == before {
+                    return None;
+                }
+            }
+            MidiMessage::ControlChange(ch, cc, _)
+                if cc == ControlFunction::ALL_NOTES_OFF || cc == ControlFunction::ALL_SOUND_OFF =>
+            {
+                self.voices.remove(&ch);
+            }
+            _ => {}
+        }
+        Some(stolen)
+    }
+
+    /// Applies `channel_rotation` to `mm`: NoteOns go to the next channel in
+    /// the list, and NoteOffs and poly aftertouch follow their note there.
+    fn rotate<'a>(&mut self, cfg: &Config, mm: MidiMessage<'a>) -> MidiMessage<'a> {
+        let channels = match &cfg.channel_rotation {
+            Some(channels) if !channels.is_empty() => channels,
+            _ => return mm,
+        };
+        match mm {
+            MidiMessage::NoteOn(ch, note, velocity) if velocity != U7::MIN => {
+                let to = channels[self.next_rotation % channels.len()];
+                self.next_rotation = (self.next_rotation + 1) % channels.len();
+                self.rotated.entry((ch, note)).or_default().push_back(to);
+                MidiMessage::NoteOn(to, note, velocity)
+            }
+            // At velocity 0 it's a NoteOff.
+            MidiMessage::NoteOn(ch, note, velocity) => {
+                MidiMessage::NoteOn(self.unrotate(ch, note), note, velocity)
+            }
+            MidiMessage::NoteOff(ch, note, velocity) => {
+                MidiMessage::NoteOff(self.unrotate(ch, note), note, velocity)
+            }
+            MidiMessage::PolyphonicKeyPressure(ch, note, pressure) => {
+                // The latest of them is the one being played.
+                let to = self
+                    .rotated
+                    .get(&(ch, note))
+                    .and_then(|started| started.back().copied())
+                    .unwrap_or(ch);
+                MidiMessage::PolyphonicKeyPressure(to, note, pressure)
+            }
+            other => other,
+        }
+    }
+
+    /// Applies `mpe` to `mm`, returning what has to go out ahead of it on
+    /// the member channel a NoteOn gets, or `None` if there's no note for
+    /// its expression to go to.
+    fn mpe<'a>(
+        &mut self,
+        cfg: &Config,
+        mm: MidiMessage<'a>,
+        ahead: &mut Vec<MidiMessage<'static>>,
+    ) -> Option<MidiMessage<'a>> {
+        let newest = |voices: &VecDeque<(Channel, (Channel, Note))>| {
+            voices.back().map(|(member, _)| *member)
+        };
+        Some(match mm {
+            MidiMessage::NoteOn(ch, note, velocity) if velocity != U7::MIN => {
+                let free = cfg
+                    .mpe_channels
+                    .iter()
+                    .copied()
+                    .find(|c| self.mpe_voices.iter().all(|(member, _)| member != c));
+                let member = match free {
+                    Some(member) => member,
+                    None => {
+                        let (member, (_, oldest)) = self.mpe_voices.pop_front()?;
+                        ahead.push(MidiMessage::NoteOff(member, oldest, cfg.note_off_velocity));
+                        member
+                    }
+                };
+                // Whatever the last note on the channel was bent to.
+                let center = centered_float_to_u14(0.0);
+                if self.last_bend.insert(member, center).unwrap_or(center) != center {
+                    ahead.push(MidiMessage::PitchBendChange(member, center));
+                }
+                self.mpe_voices.push_back((member, (ch, note)));
+                MidiMessage::NoteOn(member, note, velocity)
+            }
+            // At velocity 0 it's a NoteOff.
+            MidiMessage::NoteOn(ch, note, velocity) => {
+                MidiMessage::NoteOn(self.mpe_stop(ch, note)?, note, velocity)
+            }
+            MidiMessage::NoteOff(ch, note, velocity) => {
+                MidiMessage::NoteOff(self.mpe_stop(ch, note)?, note, velocity)
+            }
+            MidiMessage::PolyphonicKeyPressure(ch, note, pressure) => {
+                let (member, _) = self
+                    .mpe_voices
+                    .iter()
+                    .rev()
+                    .find(|(_, n)| *n == (ch, note))?;
+                MidiMessage::ChannelPressure(*member, pressure)
+            }
+            // Sent for member channels already, e.g. by `panic_combo`.
+            MidiMessage::PitchBendChange(ch, _) | MidiMessage::ChannelPressure(ch, _)
+                if cfg.mpe_channels.contains(&ch) =>
+            {
+                mm
+            }
+            MidiMessage::PitchBendChange(_, value) => {
+                MidiMessage::PitchBendChange(newest(&self.mpe_voices)?, value)
+            }
+            MidiMessage::ChannelPressure(_, value) => {
+                MidiMessage::ChannelPressure(newest(&self.mpe_voices)?, value)
+            }
+            other => other,
+        })
+    }
+
+    /// The member channel a stopping `note` on `ch` was started on, or
+    /// `None` if it was already stopped to make room for another.
+    fn mpe_stop(&mut self, ch: Channel, note: Note) -> Option<Channel> {
+        let i = self.mpe_voices.iter().position(|(_, n)| *n == (ch, note))?;
+        self.mpe_voices.remove(i).map(|(member, _)| member)
+    }
+
+    /// The channel a stopping `note` on `ch` was started on.
+    fn unrotate(&mut self, ch: Channel, note: Note) -> Channel {
+        let started = match self.rotated.get_mut(&(ch, note)) {
+            Some(started) => started,
+            None => return ch,
+        };
+        let to = started.pop_front().unwrap_or(ch);
+        if started.is_empty() {
+            self.rotated.remove(&(ch, note));
+        }
+        to
+    }
+
+    /// Applies `velocity_humanize` to a NoteOn, keeping it within 1..=127.
+    fn humanize<'a>(&mut self, cfg: &Config, mm: MidiMessage<'a>) -> MidiMessage<'a> {
+        match mm {
+            MidiMessage::NoteOn(ch, note, velocity)
+                if cfg.velocity_humanize > 0 && velocity != U7::MIN =>
+            {
+                let spread = i16::from(cfg.velocity_humanize);
+                let rng = self.rng.get_or_insert_with(|| match cfg.humanize_seed {
+                    Some(seed) => SmallRng::seed_from_u64(seed),
+                    None => SmallRng::from_entropy(),
+                });
+                let v = i16::from(u8::from(velocity)) + rng.gen_range(-spread..=spread);
+                MidiMessage::NoteOn(ch, note, U7::from_u8_lossy(v.clamp(1, 127) as u8))
+            }
+            other => other,
+        }
+    }
+
+    /// Applies `cc_rate_limit`, returning false (and keeping the value for
+    /// `tick` to send later) if `mm` is a CC that has to wait.
+    fn throttle(&mut self, cfg: &Config, mm: &MidiMessage, now: Instant) -> bool {
+        let (ch, cc, value) = match *mm {
+            MidiMessage::ControlChange(ch, cc, value) if cfg.cc_rate_limit.is_some() => {
+                (ch, cc, value)
+            }
+            _ => return true,
+        };
+        if is_command_cc(cc) {
+            return true;
+        }
+        let interval = self.cc_interval(cfg);
+        match self.cc_sent_at.get(&(ch, cc)) {
+            Some(sent_at) if now.duration_since(*sent_at) < interval => {
+                self.cc_held_back.insert((ch, cc), value);
+                false
+            }
+            _ => {
+                self.cc_held_back.remove(&(ch, cc));
+                self.cc_sent_at.insert((ch, cc), now);
+                true
+            }
+        }
+    }
+
+    fn cc_interval(&self, cfg: &Config) -> Duration {
+        let rate = cfg.cc_rate_limit.unwrap_or(u32::MAX).max(1);
+        Duration::from_secs(1) / rate
+    }
+
+    /// Whether `mm` is worth sending. Control changes and pitch bends that
+    /// repeat the last value sent are dropped; everything else goes through.
+    fn should_send(&mut self, mm: &MidiMessage) -> bool {
+        match *mm {
+            MidiMessage::ControlChange(_, cc, _) if is_command_cc(cc) => true,
+            MidiMessage::ControlChange(ch, cc, value) => {
+                let changed = self.last_cc.insert((ch, cc), value) != Some(value);
+                if changed && u8::from(cc.0) < 32 {
+                    // A new MSB resets the receiver's LSB, so the next LSB
+                    // has to go out even if it's the same as last time.
+                    self.last_cc
+                        .remove(&(ch, ControlFunction(U7::from_u8_lossy(u8::from(cc.0) + 32))));
+                }
+                changed
+            }
+            MidiMessage::PitchBendChange(ch, value) => {
+                self.last_bend.insert(ch, value) != Some(value)
+            }
+            _ => true,
+        }
+    }
+
+    /// What to actually send for `mm` once `channel_rotation` or `mpe`,
+    /// `velocity_humanize`, `cc_rate_limit`, repeated values and `max_voices`
+    /// have had their say: nothing, or what has to go ahead of it (the
+    /// NoteOffs for any voices it steals, and a pitch bend reset for the
+    /// channel an MPE note gets) and then `mm`.
+    pub fn outgoing<'a>(
+        &mut self,
+        cfg: &Config,
+        mm: MidiMessage<'a>,
+        now: Instant,
+    ) -> Option<(Vec<MidiMessage<'static>>, MidiMessage<'a>)> {
+        let mut ahead = Vec::new();
+        let mm = if cfg.mpe {
+            self.mpe(cfg, mm, &mut ahead)?
+        } else {
+            self.rotate(cfg, mm)
+        };
+        let mm = self.humanize(cfg, mm);
+        if !self.throttle(cfg, &mm, now) || !self.should_send(&mm) {
+            return None;
+        }
+        ahead.extend(self.allocate_voice(cfg, &mm)?);
+        self.used_channels
+            .extend(ahead.iter().chain(Some(&mm)).filter_map(|mm| mm.channel()));
+        Some((ahead, mm))
+    }
+
+    /// What `panic_combo` sends.
+    fn panic(&mut self, cfg: &Config) -> Vec<MidiMessage<'static>> {
+        self.pending_press.clear();
+        self.macro_queue.clear();
+        let mut out: Vec<_> = self
+            .release_all()
+            .into_iter()
+            .map(|(ch, note)| MidiMessage::NoteOff(ch, note, cfg.note_off_velocity))
+            .collect();
+        self.sustain.clear();
+        // Whatever got stuck may well have been a repeat of the last value,
+        // none of this should be dropped as one.
+        self.last_cc.clear();
+        self.last_bend.clear();
+        for ch in self.used_channels.iter().copied() {
+            out.push(MidiMessage::ControlChange(
+                ch,
+                ControlFunction::DAMPER_PEDAL,
+                U7::MIN,
+            ));
+            out.push(MidiMessage::PitchBendChange(ch, centered_float_to_u14(0.0)));
+            out.push(MidiMessage::ControlChange(
+                ch,
+                ControlFunction::ALL_SOUND_OFF,
+                U7::MIN,
+            ));
+            out.push(MidiMessage::ControlChange(
+                ch,
+                ControlFunction::ALL_NOTES_OFF,
+                U7::MIN,
+            ));
+        }
+        out
+    }
+
+    /// Stops the notes `old` started when `new` replaces it, rather than
+    /// leave them to releases the new mapping may not know about.
+    pub fn reload(&mut self, old: &Config, new: &Config) -> Vec<MidiMessage<'static>> {
+        self.pending_press.clear();
+        let offs = self
+            .release_all()
+            .into_iter()
+            .map(|(ch, note)| MidiMessage::NoteOff(ch, note, old.note_off_velocity))
+            .collect();
+        if new.clock_bpm != old.clock_bpm {
+            self.clock_bpm = new.clock_bpm;
+        }
+        if self.profile > new.profiles.len() {
+            self.profile = 0;
+        }
+        if new.invert_toggle_button.is_none() {
+            self.axes_inverted = false;
+        }
+        if new.humanize_seed != old.humanize_seed {
+            self.rng = None;
+        }
+        self.pad_profiles = self
+            .names
+            .iter()
+            .filter_map(|(id, name)| Some((*id, new.gamepad_profile(name)?)))
+            .collect();
+        offs
+    }
+
+    /// What to send on the way out so that nothing is left sounding.
+    pub fn shutdown(&mut self, cfg: &Config) -> Vec<MidiMessage<'static>> {
+        let mut out: Vec<_> = self
+            .release_all()
+            .into_iter()
+            .map(|(ch, note)| MidiMessage::NoteOff(ch, note, cfg.note_off_velocity))
+            .collect();
+        if self.clock_running {
+            out.push(MidiMessage::Stop);
+        }
+        // All Notes Off leaves sustained notes ringing, lift the pedal first.
+        out.extend(
+            self.sustain
+                .drain()
+                .map(|ch| MidiMessage::ControlChange(ch, ControlFunction::DAMPER_PEDAL, U7::MIN)),
+        );
+        out.extend(
+            self.used_channels
+                .iter()
+                .map(|ch| MidiMessage::ControlChange(*ch, ControlFunction::ALL_NOTES_OFF, U7::MIN)),
+        );
+        out
+    }
+}
+
+/// Works out what a gamepad event sends, updating `state` along the way.
+/// Everything timed (arpeggios, clock, macros, drum offs, ...) is left to
+/// `State::tick`, and the caller is expected to pass what comes back
+/// through `Output::send_all`, which applies the per-message limits.
+///
+/// `at` is when the event happened, as placed by `EventClock`. Anything
+/// `tick` sends later for it is scheduled from then rather than from when
+/// the event got read.
+pub fn map_event(
+    cfg: &Config,
+    state: &mut State,
+    ev: &Event,
+    at: Instant,
+) -> Vec<MidiMessage<'static>> {
+    let Event { id, event, time } = *ev;
+    let mut out = Vec::new();
+    let channel = state.channel_for(cfg, id);
+    match event {
+        EventType::ButtonChanged(btn, pos, code) => {
+            log::debug!("{:?} {} {:?} {} {}", time, id, btn, pos, code);
+            let channel = cfg.button_channel(btn, channel);
+            state.button_pos.insert((id, btn), pos);
+            if let Some((ch, notes)) = state.pending_press.remove(&(id, btn)) {
+                let velocity = state.press_velocity(cfg, id, btn, time, Some(pos));
+                state.start_notes(cfg, (id, btn), (ch, notes), velocity, at, &mut out);
+            }
+            if cfg.release_velocity_from_speed {
+                state.track_fall((id, btn), pos, time);
+            }
+            let shaped = cfg.shape_button(btn, pos);
+            if let Some(cc) = state.mapping(cfg, id).analog_button_ccs.get(&btn) {
+                out.push(MidiMessage::ControlChange(
+                    channel,
+                    ControlFunction::from(*cc),
+                    abs_float_to_midi(shaped),
+                ));
+            }
+            if cfg.analog_button_aftertouch.contains(&btn) {
+                out.push(MidiMessage::ChannelPressure(
+                    channel,
+                    abs_float_to_midi(shaped),
+                ));
+            }
+            if let Some(keys) = cfg.poly_aftertouch_buttons.get(&btn) {
+                let pressure = abs_float_to_midi(shaped);
+                for ((pad, key), (ch, notes)) in state.held.iter() {
+                    if *pad == id && (keys.is_empty() || keys.contains(key)) {
+                        out.extend(
+                            notes.iter().map(|note| {
+                                MidiMessage::PolyphonicKeyPressure(*ch, *note, pressure)
+                            }),
+                        );
+                    }
+                }
+            }
+            if let Some(param) = cfg.analog_button_nrpn.get(&btn) {
+                state.nrpn(channel, *param, abs_float_to_u14(shaped), &mut out);
+            }
+            if let Some((note, on, off)) = cfg.trigger_note_threshold.get(&btn) {
+                let playing = state.trigger_held.get(&(id, btn)).copied();
+                match playing {
+                    None if pos >= *on => {
+                        let shift = cfg.transpose as i16 + state.octave_shift;
+                        let note = cfg.quantize(transpose(*note, shift));
+                        let velocity = state.press_velocity(cfg, id, btn, time, Some(pos));
+                        out.push(MidiMessage::NoteOn(channel, note, velocity));
+                        state.trigger_held.insert((id, btn), (channel, note));
+                    }
+                    Some((ch, note)) if pos <= *off => {
+                        out.push(MidiMessage::NoteOff(ch, note, cfg.note_off_velocity));
+                        state.trigger_held.remove(&(id, btn));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        EventType::ButtonPressed(btn, code) => {
+            log::debug!("{:?} {} {:?} press {}", time, id, btn, code);
+            state.pressed.insert((id, btn));
+            state.update_combos(cfg, (id, btn), channel, &mut out);
+            let channel = cfg.button_channel(btn, channel);
+            let panic = &cfg.panic_combo;
+            let combo = &cfg.profile_combo;
+            if let Some(cc) = cfg.catchall_cc(btn, code.into_u32()) {
+                log::info!(
+                    "Unmapped {:?} (code {}) sends CC {}",
+                    btn,
+                    code.into_u32(),
+                    u8::from(cc.0)
+                );
+                out.push(MidiMessage::ControlChange(channel, cc, U7::MAX));
+            } else if btn == Button::Unknown {
+                match cfg.raw_button_notes.get(&code.into_u32()) {
+                    Some(note) => {
+                        let shift = cfg.transpose as i16 + state.octave_shift;
+                        let note = cfg.quantize(transpose(*note, shift));
+                        let velocity = state.press_velocity(cfg, id, btn, time, None);
+                        out.push(MidiMessage::NoteOn(channel, note, velocity));
+                        state
+                            .raw_held
+                            .insert((id, code.into_u32()), (channel, note));
+                    }
+                    None => log::debug!("Unmapped raw button code {}", code.into_u32()),
+                }
+            } else if panic.contains(&btn)
+                && panic.iter().all(|b| state.pressed.contains(&(id, *b)))
+            {
+                log::info!("Panic: stopping everything");
+                out.extend(state.panic(cfg));
+            } else if combo.contains(&btn)
+                && combo.iter().all(|b| state.pressed.contains(&(id, *b)))
+            {
+                // Whatever the rest of the combo started belongs to
+                // the old profile, stop it along with everything else.
+                let profiles = cfg.profiles.len() + 1;
+                let profile = match state.pad_profiles.get_mut(&id) {
+                    Some(profile) => profile,
+                    None => &mut state.profile,
+                };
+                *profile = (*profile + 1) % profiles;
+                log::info!("Profile: {}", cfg.profile_name(*profile));
+                state.pending_press.clear();
+                for (ch, note) in state.release_all() {
+                    out.push(MidiMessage::NoteOff(ch, note, cfg.note_off_velocity));
+                }
+                for ch in state.used_channels.iter() {
+                    out.push(MidiMessage::ControlChange(
+                        *ch,
+                        ControlFunction::ALL_NOTES_OFF,
+                        U7::MIN,
+                    ));
+                }
+            } else if Some(btn) == cfg.shift_button {
+                state.shifted.insert(id);
+            } else if Some(btn) == cfg.strum_down_button {
+                // Only looked at when a strum starts.
+            } else if Some(btn) == cfg.tap_tempo_button {
+                state.tap(at);
+            } else if Some(btn) == cfg.clock_start_button {
+                // Start the next clock tick along with the song.
+                state.next_clock = None;
+                state.clock_running = true;
+                out.push(MidiMessage::Start);
+            } else if Some(btn) == cfg.clock_stop_button {
+                state.clock_running = false;
+                out.push(MidiMessage::Stop);
+            } else if let Some(transport) = cfg.transport_buttons.get(&btn) {
+                state.clock_running = *transport != Transport::Stop;
+                if state.clock_running {
+                    state.next_clock = None;
+                }
+                out.push(match transport {
+                    Transport::Start => MidiMessage::Start,
+                    Transport::Stop => MidiMessage::Stop,
+                    Transport::Continue => MidiMessage::Continue,
+                });
+            } else if Some(btn) == cfg.octave_up_button || Some(btn) == cfg.octave_down_button {
+                let step = if Some(btn) == cfg.octave_up_button {
+                    12
+                } else {
+                    -12
+                };
+                state.octave_shift = (state.octave_shift + step).clamp(-120, 120);
+                log::info!("Octave shift: {:+}", state.octave_shift / 12);
+            } else if Some(btn) == cfg.invert_toggle_button {
+                state.axes_inverted = !state.axes_inverted;
+                log::info!(
+                    "Axes {}",
+                    if state.axes_inverted {
+                        "inverted"
+                    } else {
+                        "back the right way"
+                    }
+                );
+                if cfg.invert_toggle_resend {
+                    state.flip_axes(cfg, at, &mut out);
+                }
+            } else if let Some(action) = cfg.program_buttons.get(&btn) {
+                let bank = state.bank.entry(channel).or_insert(0);
+                let program = state.program.entry(channel).or_insert(U7::MIN);
+                let n = match action {
+                    ProgramButton::Step(ProgramStep::Next) => (u8::from(*program) + 1) % 128,
+                    ProgramButton::Step(ProgramStep::Prev) => (u8::from(*program) + 127) % 128,
+                    ProgramButton::Program(n) => {
+                        let new_bank = (n / 128) as u16;
+                        if new_bank != *bank {
+                            *bank = new_bank;
+                            out.push(MidiMessage::ControlChange(
+                                channel,
+                                ControlFunction::BANK_SELECT,
+                                U7::from_u8_lossy((new_bank >> 7) as u8),
+                            ));
+                            out.push(MidiMessage::ControlChange(
+                                channel,
+                                ControlFunction::BANK_SELECT_LSB,
+                                U7::from_u8_lossy((new_bank & 0x7f) as u8),
+                            ));
+                        }
+                        (n % 128) as u8
+                    }
+                };
+                *program = U7::from_u8_lossy(n);
+                log::info!(
+                    "Channel {} program: {} (bank {})",
+                    channel.number(),
+                    n,
+                    bank
+                );
+                out.push(MidiMessage::ProgramChange(channel, *program));
+            } else if let Some((msb, lsb, program)) = cfg.program_bank.get(&btn) {
+                let bank = (u16::from(u8::from(*msb)) << 7) | u16::from(u8::from(*lsb));
+                state.bank.insert(channel, bank);
+                state.program.insert(channel, *program);
+                log::info!(
+                    "Channel {} program: {} (bank {})",
+                    channel.number(),
+                    u8::from(*program),
+                    bank
+                );
+                out.push(MidiMessage::ControlChange(
+                    channel,
+                    ControlFunction::BANK_SELECT,
+                    *msb,
+                ));
+                out.push(MidiMessage::ControlChange(
+                    channel,
+                    ControlFunction::BANK_SELECT_LSB,
+                    *lsb,
+                ));
+                out.push(MidiMessage::ProgramChange(channel, *program));
+            } else if Some(btn) == cfg.sustain_button {
+                let down = !(cfg.sustain_toggle && state.sustain.contains(&channel));
+                if down {
+                    state.sustain.insert(channel);
+                } else {
+                    state.sustain.remove(&channel);
+                }
+                out.push(MidiMessage::ControlChange(
+                    channel,
+                    ControlFunction::DAMPER_PEDAL,
+                    if down { U7::MAX } else { U7::MIN },
+                ));
+            } else if let Some(cc) = cfg.button_toggle_ccs.get(&btn) {
+                out.push(MidiMessage::ControlChange(
+                    channel,
+                    ControlFunction(*cc),
+                    U7::MAX,
+                ));
+            } else if let Some(bytes) = cfg.sysex_buttons.get(&btn) {
+                out.push(sysex_message(bytes));
+            } else if let Some(bytes) = cfg.raw_button_bytes.get(&btn) {
+                state.raw_out.push(bytes.clone());
+            } else if let Some(steps) = cfg.macros.get(&btn) {
+                // Everything goes out from `tick`, even steps with no
+                // delay, so they can't get ahead of earlier ones.
+                state.start_macro(Some((id, btn)), channel, steps, at);
+            } else if cfg.latching_buttons.contains(&btn) && state.is_sounding(id, btn) {
+                state.stop_button(cfg, (id, btn), cfg.note_off_velocity, &mut out);
+            } else if let Some(notes) = state.press_notes(cfg, id, btn) {
+                let shift = cfg.transpose as i16 + state.octave_shift;
+                let mut notes: Vec<_> = notes
+                    .into_iter()
+                    .map(|note| cfg.quantize(transpose(note, shift)))
+                    .collect();
+                // Quantizing can land two chord notes on one, which
+                // should only start (and stop) once.
+                notes.sort();
+                notes.dedup();
+                if cfg.velocity_mode == VelocityMode::FromPressure {
+                    // gilrs queues a ButtonChanged with the position
+                    // right behind the press, wait for that.
+                    state.pending_press.insert((id, btn), (channel, notes));
+                } else {
+                    let velocity = state.press_velocity(cfg, id, btn, time, None);
+                    state.start_notes(cfg, (id, btn), (channel, notes), velocity, at, &mut out);
+                }
+            }
+        }
+        EventType::ButtonReleased(btn, code) => {
+            log::debug!("{:?} {} {:?} release {}", time, id, btn, code);
+            state.pressed.remove(&(id, btn));
+            state.update_combos(cfg, (id, btn), channel, &mut out);
+            let channel = cfg.button_channel(btn, channel);
+            if let Some(cc) = cfg.catchall_cc(btn, code.into_u32()) {
+                out.push(MidiMessage::ControlChange(channel, cc, U7::MIN));
+            } else if btn == Button::Unknown {
+                if let Some((ch, note)) = state.raw_held.remove(&(id, code.into_u32())) {
+                    out.push(MidiMessage::NoteOff(ch, note, cfg.note_off_velocity));
+                }
+            } else if Some(btn) == cfg.shift_button {
+                // Notes started on either layer stay in `held` as
+                // played, so releasing them after this still works.
+                state.shifted.remove(&id);
+            } else if Some(btn) == cfg.sustain_button {
+                if !cfg.sustain_toggle && state.sustain.remove(&channel) {
+                    out.push(MidiMessage::ControlChange(
+                        channel,
+                        ControlFunction::DAMPER_PEDAL,
+                        U7::MIN,
+                    ));
+                }
+            } else if let Some(cc) = cfg.button_toggle_ccs.get(&btn) {
+                out.push(MidiMessage::ControlChange(
+                    channel,
+                    ControlFunction(*cc),
+                    U7::MIN,
+                ));
+            } else if !cfg.latching_buttons.contains(&btn) {
+                state.pending_press.remove(&(id, btn));
+                let velocity = state.release_velocity(cfg, (id, btn));
+                state.stop_button(cfg, (id, btn), velocity, &mut out);
+            }
+        }
+        EventType::ButtonRepeated(btn, code) => {
+            let held = state.held.get(&(id, btn));
+            match held {
+                Some((ch, notes)) if cfg.repeat_as_retrigger.contains(&btn) && !cfg.mono => {
+                    for note in notes.iter() {
+                        out.push(MidiMessage::NoteOff(*ch, *note, cfg.note_off_velocity));
+                        out.push(MidiMessage::NoteOn(*ch, *note, cfg.button_velocity(btn)));
+                    }
+                }
+                _ => log::debug!("{:?} {} {:?} repeated {}, ignoring", time, id, btn, code),
+            }
+        }
+        EventType::AxisChanged(ax, pos, code) => {
+            log::debug!("{:?} {} {:?} {} {}", time, id, ax, pos, code);
+            let channel = cfg.axis_channel(ax, channel);
+            if ax == Axis::Unknown {
+                match cfg.raw_axis_ccs.get(&code.into_u32()) {
+                    Some(cc) => out.push(MidiMessage::ControlChange(
+                        channel,
+                        ControlFunction::from(*cc),
+                        centered_float_to_midi(apply_deadzone(pos, cfg.deadzone)),
+                    )),
+                    None => log::debug!("Unmapped raw axis code {}", code.into_u32()),
+                }
+            }
+            let pos = state.calibrate_axis(cfg, ax, pos);
+            state.move_axis(cfg, (id, ax), channel, pos, at, &mut out);
+        }
+        EventType::Disconnected => {
+            state.shifted.remove(&id);
+            for (ch, note) in state.release_gamepad(id) {
+                out.push(MidiMessage::NoteOff(ch, note, cfg.note_off_velocity));
+            }
+        }
+        other => {
+            log::debug!("{:?} {} {:?}", time, id, other);
+        }
+    }
+    out
+}
+
+/// Moves a mono voice from the notes in `from` to those in `to`, overlapping
+/// them with `legato`. Notes in both keep sounding.
+fn switch_notes(
+    cfg: &Config,
+    channel: Channel,
+    from: &[Note],
+    to: &[Note],
+    velocity: U7,
+    out: &mut Vec<MidiMessage<'static>>,
+) {
+    let note_offs = from
+        .iter()
+        .filter(|note| !to.contains(note))
+        .map(|note| MidiMessage::NoteOff(channel, *note, cfg.note_off_velocity));
+    let note_ons = to
+        .iter()
+        .filter(|note| !from.contains(note))
+        .map(|note| MidiMessage::NoteOn(channel, *note, velocity));
+    if cfg.legato {
+        out.extend(note_ons);
+        out.extend(note_offs);
+    } else {
+        out.extend(note_offs);
+        out.extend(note_ons);
+    }
+}
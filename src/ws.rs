@@ -0,0 +1,53 @@
+//! `--ws`: the `--json` lines streamed to WebSocket clients, for browser
+//! visualizers and the like.
+
+use eyre::{Result, WrapErr};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use tungstenite::{Message, WebSocket};
+
+type Clients = Arc<Mutex<Vec<WebSocket<TcpStream>>>>;
+
+/// Listens for clients on `port` on this machine, and returns where to send
+/// the lines they should all get. A client that connects part way through
+/// gets whatever's sent from then on.
+pub fn serve(port: u16) -> Result<mpsc::Sender<String>> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .wrap_err_with(|| format!("listening for WebSocket clients on port {}", port))?;
+    log::info!("Serving WebSocket clients on ws://127.0.0.1:{}", port);
+    let clients = Clients::default();
+    {
+        let clients = clients.clone();
+        std::thread::spawn(move || accept(listener, clients));
+    }
+    let (tx, rx) = mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        for line in rx {
+            let mut clients = clients.lock().unwrap_or_else(|e| e.into_inner());
+            // A client that can't be written to has gone.
+            clients.retain_mut(|ws| ws.send(Message::text(line.clone())).is_ok());
+        }
+    });
+    Ok(tx)
+}
+
+fn accept(listener: TcpListener, clients: Clients) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Accepting a WebSocket client: {}", e);
+                continue;
+            }
+        };
+        let peer = stream.peer_addr().ok();
+        let _ = stream.set_nodelay(true);
+        match tungstenite::accept(stream) {
+            Ok(ws) => {
+                log::info!("WebSocket client {:?} connected", peer);
+                clients.lock().unwrap_or_else(|e| e.into_inner()).push(ws);
+            }
+            Err(e) => log::warn!("WebSocket handshake with {:?}: {}", peer, e),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Loading configs: what parses, what `Config::load` turns away and why.
+
+use gamepad2midi::{Calibration, Config, MessageKind, Route};
+use gilrs::{Axis, Button};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wmidi::{Channel, ControlFunction, MidiMessage, Note, U7};
+
+/// Loads `text` the way `--config` would, through a file of its own.
+fn load(text: &str) -> eyre::Result<Config> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "gamepad2midi-test-{}-{}.toml",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::write(&path, text).unwrap();
+    let cfg = Config::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    cfg
+}
+
+/// The whole error chain, the way `main` prints it.
+fn error(text: &str) -> String {
+    format!("{:#}", load(text).unwrap_err())
+}
+
+#[track_caller]
+fn assert_error(text: &str, want: &str) {
+    let got = error(text);
+    assert!(got.contains(want), "{:?} doesn't mention {:?}", got, want);
+}
+
+#[test]
+fn default_round_trips() {
+    let cfg = Config::default();
+    assert_eq!(load(&cfg.to_toml().unwrap()).unwrap(), cfg);
+}
+
+#[test]
+fn empty_is_default() {
+    assert_eq!(load("").unwrap(), Config::default());
+}
+
+#[test]
+fn names_and_numbers() {
+    let cfg = load(
+        r#"
+        output_midi_channel = 1
+        keys = { South = "C#3", North = 60, East = "Bb-1" }
+        axis_ccs = { RightZ = 11 }
+        raw_axis_ccs = { "196612" = 12 }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(cfg.output_midi_channel, Channel::Ch1);
+    assert_eq!(cfg.keys[&Button::South], Note::Db3);
+    assert_eq!(cfg.keys[&Button::North], Note::C4);
+    assert_eq!(cfg.keys[&Button::East], Note::ASharpMinus1);
+    assert_eq!(cfg.axis_ccs[&Axis::RightZ], U7::from_u8_lossy(11));
+    assert_eq!(cfg.raw_axis_ccs[&196612], U7::from_u8_lossy(12));
+}
+
+#[test]
+fn out_of_range_numbers() {
+    assert_error("velocity = 200", "200 is out of range, must be 0..=127");
+    assert_error("note_off_velocity = 200", "200 is out of range");
+    assert_error("button_velocity = { South = 128 }", "128 is out of range");
+    assert_error("axis_ccs = { LeftStickX = 128 }", "128 is out of range");
+    assert_error(
+        "analog_button_ccs = { LeftTrigger2 = -1 }",
+        "-1 is out of range",
+    );
+    assert_error(
+        "axis_range = { LeftStickX = [20, 300] }",
+        "300 is out of range",
+    );
+    assert_error(
+        "axis_curve_table = { LeftStickX = [0, 200] }",
+        "200 is out of range",
+    );
+    assert_error(
+        "output_midi_channel = 17",
+        "channel 17 is out of range, must be 1..=16",
+    );
+    assert_error("output_midi_channel = 0", "channel 0 is out of range");
+    assert_error(
+        "program_bank = { West = [0, 3, 128] }",
+        "128 is out of range",
+    );
+}
+
+#[test]
+fn names_that_dont_parse() {
+    assert_error("keys = { South = 128 }", "a MIDI note number 0..=127");
+    assert_error("keys = { South = \"H2\" }", "a note name");
+    assert_error("keys = { Middle = \"C2\" }", "a gamepad button name");
+}
+
+#[test]
+fn validation() {
+    for (text, want) in [
+        ("velocity = 0", "velocity must be at least 1"),
+        (
+            "button_velocity = { South = 0 }",
+            "button_velocity: South must be at least 1",
+        ),
+        ("deadzone = 1.0", "deadzone is 1, it has to be at least 0 and below 1"),
+        (
+            "axis_deadzones = { LeftStickX = -0.1 }",
+            "axis_deadzones: LeftStickX's deadzone is -0.1",
+        ),
+        (
+            "axis_range = { LeftStickX = [100, 20] }",
+            "min has to be below max",
+        ),
+        (
+            "trigger_note_threshold = { LeftTrigger2 = [\"C2\", 0.4, 0.6] }",
+            "needs 0 <= off < on <= 1",
+        ),
+        (
+            "axis_calibration = { LeftStickX = { center = -0.9, min = -0.5, max = 1.0 } }",
+            "needs min < center < max",
+        ),
+        (
+            "combo_notes = [{ buttons = [\"North\"], note = \"C4\" }]",
+            "needs at least two buttons",
+        ),
+        (
+            "modifier_notes = { South = { notes = [], axis = \"LeftZ\" } }",
+            "modifier_notes: South has no notes",
+        ),
+        (
+            "modifier_notes = { South = { notes = [\"C2\"] } }",
+            "needs exactly one of button and axis",
+        ),
+        (
+            "modifier_notes = { South = { notes = [\"C2\"], axis = \"LeftZ\", button = \"LeftTrigger2\" } }",
+            "needs exactly one of button and axis",
+        ),
+        (
+            "axis_curve_table = { LeftStickX = [] }",
+            "LeftStickX has an empty table",
+        ),
+        (
+            "note_repeat_buttons = { West = 0 }",
+            "West repeats every 0ms",
+        ),
+        (
+            "gamepad_profiles = { xbox = \"leads\" }",
+            "which isn't in profiles",
+        ),
+        (
+            "[[profiles]]\nname = \"leads\"",
+            "profiles are set but there's no way to switch to them",
+        ),
+        (
+            "on_connect = [{ program = 1, bend = 8192 }]",
+            "on_connect: step 1 needs exactly one of note, cc, program, bend and sysex",
+        ),
+        (
+            "on_connect = [{ program = 1 }, { bend = 20000 }]",
+            "on_connect: the bend in step 2 is 20000, the highest is 16383",
+        ),
+        (
+            "macros = { South = [{ delay_ms = 10 }] }",
+            "macros: step 1 of South needs exactly one of",
+        ),
+        (
+            "catchall_button_cc_base = 120",
+            "catchall_button_cc_base is 120, it has to be below 120",
+        ),
+        (
+            "program_bank = { West = [0, 3, 12] }\nprogram_buttons = { West = \"Next\" }",
+            "program_bank: West is in program_buttons too",
+        ),
+        ("cc_rate_limit = 0", "cc_rate_limit must be at least 1"),
+        ("max_voices = 0", "max_voices must be at least 1"),
+        (
+            "max_events_per_tick = 0",
+            "max_events_per_tick must be at least 1",
+        ),
+        (
+            "channel_rotation = []",
+            "channel_rotation needs at least one channel",
+        ),
+        (
+            "mpe = true\nmpe_channels = []",
+            "mpe needs at least one channel",
+        ),
+        (
+            "mpe = true\nchannel_rotation = [1, 2]",
+            "use one or the other",
+        ),
+    ] {
+        assert_error(text, want);
+        assert!(error(text).contains("checking config"), "{}", text);
+    }
+}
+
+#[test]
+fn gamepad_profiles_name_real_profiles() {
+    let cfg = load(
+        r#"
+        gamepad_profiles = { xbox = "leads", "8bitdo" = "default" }
+
+        [[profiles]]
+        name = "leads"
+        keys = { South = "C5" }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(cfg.gamepad_profiles["xbox"], "leads");
+    assert_eq!(cfg.profiles[0].layer.keys[&Button::South], Note::C5);
+}
+
+#[test]
+fn calibration_stretches_each_side() {
+    let mut cfg = Config::default();
+    cfg.axis_calibration.insert(
+        Axis::LeftStickX,
+        Calibration {
+            center: 0.1,
+            min: -0.7,
+            max: 0.9,
+        },
+    );
+    for (pos, want) in [
+        (0.1, 0.0),
+        (-0.7, -1.0),
+        (0.9, 1.0),
+        (-0.3, -0.5),
+        (0.5, 0.5),
+        (1.0, 1.0),
+        (-1.0, -1.0),
+    ] {
+        let got = cfg.calibrate_axis(Axis::LeftStickX, pos);
+        assert!(
+            (got - want).abs() < 1e-5,
+            "{} -> {}, not {}",
+            pos,
+            got,
+            want
+        );
+    }
+    cfg.invert_axes.insert(Axis::LeftStickX);
+    assert!((cfg.calibrate_axis(Axis::LeftStickX, 0.9) + 1.0).abs() < 1e-5);
+    assert_eq!(cfg.calibrate_axis(Axis::LeftStickY, 0.3), 0.3);
+}
+
+#[test]
+fn routes_take_what_they_ask_for() {
+    let note = MidiMessage::NoteOn(Channel::Ch2, Note::C4, U7::MAX);
+    let cc = MidiMessage::ControlChange(Channel::Ch3, ControlFunction::MODULATION_WHEEL, U7::MIN);
+    let route = |messages: Vec<MessageKind>, channels: Vec<Channel>| Route {
+        port: "synth".into(),
+        messages,
+        channels,
+    };
+
+    let all = route(vec![], vec![]);
+    assert!(all.takes(&note) && all.takes(&cc) && all.takes(&MidiMessage::TimingClock));
+
+    let notes = route(vec![MessageKind::Notes], vec![]);
+    assert!(notes.takes(&note) && !notes.takes(&cc));
+
+    let ch2 = route(vec![], vec![Channel::Ch2]);
+    assert!(ch2.takes(&note) && !ch2.takes(&cc));
+    // System messages have no channel to go by.
+    assert!(ch2.takes(&MidiMessage::Start));
+
+    let clock = route(vec![MessageKind::System], vec![Channel::Ch2]);
+    assert!(clock.takes(&MidiMessage::TimingClock) && !clock.takes(&note));
+}
+
+#[test]
+fn unmapped_keeps_only_what_the_file_maps() {
+    let path = std::env::temp_dir().join(format!(
+        "gamepad2midi-test-{}-unmapped.toml",
+        std::process::id()
+    ));
+    std::fs::write(&path, "velocity = 100\nkeys = { South = \"C4\" }\n").unwrap();
+    let cfg = Config::load_unmapped(&path);
+    std::fs::remove_file(&path).unwrap();
+    let cfg = cfg.unwrap();
+    assert_eq!(cfg.keys.len(), 1);
+    assert_eq!(cfg.keys[&Button::South], Note::C4);
+    assert!(cfg.axis_ccs.is_empty() && cfg.analog_button_ccs.is_empty());
+    assert_eq!(cfg.velocity, U7::from_u8_lossy(100));
+    assert_eq!(cfg.output_port_name, Config::default().output_port_name);
+
+    assert!(Config::unmapped().keys.is_empty());
+    assert!(!load("").unwrap().keys.is_empty());
+}
+
+#[test]
+fn output_port_name_is_required() {
+    assert_error(
+        "output_port_name = \"\"",
+        "output_port_name is empty, which would match any port",
+    );
+}
@@ -0,0 +1,38 @@
+//! Placing gilrs event times on the `Instant` timeline.
+
+use gamepad2midi::EventClock;
+use std::time::{Duration, Instant, SystemTime};
+
+fn ms(n: u64) -> Duration {
+    Duration::from_millis(n)
+}
+
+#[test]
+fn first_event_is_when_its_read() {
+    let mut clock = EventClock::default();
+    let now = Instant::now();
+    assert_eq!(clock.at(SystemTime::UNIX_EPOCH, now), now);
+}
+
+#[test]
+fn later_events_keep_their_spacing() {
+    let mut clock = EventClock::default();
+    let (t0, now) = (SystemTime::now(), Instant::now());
+    clock.at(t0, now);
+    // Read late, but placed when it happened.
+    assert_eq!(clock.at(t0 + ms(10), now + ms(25)), now + ms(10));
+    assert_eq!(clock.at(t0 + ms(12), now + ms(25)), now + ms(12));
+}
+
+#[test]
+fn events_never_go_back_or_ahead() {
+    let mut clock = EventClock::default();
+    let (t0, now) = (SystemTime::now(), Instant::now());
+    clock.at(t0, now);
+    assert_eq!(clock.at(t0 + ms(20), now + ms(30)), now + ms(20));
+    // The system clock went back.
+    assert_eq!(clock.at(t0 + ms(5), now + ms(31)), now + ms(20));
+    assert_eq!(clock.at(t0 - ms(100), now + ms(32)), now + ms(20));
+    // Or forward: it can't have happened after it was read.
+    assert_eq!(clock.at(t0 + ms(5000), now + ms(40)), now + ms(40));
+}
@@ -0,0 +1,43 @@
+//! Picking an output port out of the names midir lists.
+
+use gamepad2midi::find_port;
+
+fn ports() -> Vec<String> {
+    vec![
+        "Midi Through:Midi Through Port-0 14:0".to_string(),
+        "loopMIDI Port".to_string(),
+        "loopMIDI Port 2".to_string(),
+        "USB Uno MIDI Interface".to_string(),
+    ]
+}
+
+#[test]
+fn exact_name_wins() {
+    assert_eq!(find_port(&ports(), "loopMIDI Port 2").unwrap(), Some(2));
+    // Even though it's also a substring of the one after it.
+    assert_eq!(find_port(&ports(), "loopMIDI Port").unwrap(), Some(1));
+}
+
+#[test]
+fn substring_matches() {
+    assert_eq!(find_port(&ports(), "Uno").unwrap(), Some(3));
+    assert_eq!(find_port(&ports(), "Through").unwrap(), Some(0));
+}
+
+#[test]
+fn case_is_ignored() {
+    assert_eq!(find_port(&ports(), "usb uno").unwrap(), Some(3));
+    assert_eq!(find_port(&ports(), "MIDI THROUGH").unwrap(), Some(0));
+}
+
+#[test]
+fn no_match() {
+    assert_eq!(find_port(&ports(), "IAC Driver").unwrap(), None);
+    assert_eq!(find_port(&[], "loopMIDI").unwrap(), None);
+}
+
+#[test]
+fn several_matches_take_the_first() {
+    assert_eq!(find_port(&ports(), "loopmidi").unwrap(), Some(1));
+    assert_eq!(find_port(&ports(), "midi").unwrap(), Some(0));
+}
@@ -0,0 +1,1463 @@
+//! Feeds synthetic gamepad events through `map_event` (and `State::tick` for
+//! anything timed) and checks what comes out.
+
+use gamepad2midi::{
+    is_midi, map_event, ComboNote, Config, Curve, Layer, MacroStep, ModifierNotes, Profile,
+    ProgramButton, ProgramStep, State, Transport, VelocityMode,
+};
+use gilrs::ev::Code;
+use gilrs::{Axis, Button, Event, EventType, GamepadId};
+use std::convert::TryFrom;
+use std::time::{Duration, Instant, SystemTime};
+use wmidi::{Channel, ControlFunction, MidiMessage, Note, U14, U7};
+
+/// gilrs doesn't let ids be made outside of a real connection, but with
+/// `serde-serialize` they can be read back in.
+fn pad(n: usize) -> GamepadId {
+    serde_json::from_value(n.into()).unwrap()
+}
+
+/// A button or axis code, from the same trick. What they hold differs by
+/// platform, and which one it is doesn't matter to the mapping.
+fn code() -> Code {
+    #[cfg(target_os = "linux")]
+    let raw = serde_json::json!({ "kind": 1, "code": 304 });
+    #[cfg(target_os = "macos")]
+    let raw = serde_json::json!({ "page": 9, "usage": 1 });
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let raw = serde_json::json!(0);
+    serde_json::from_value(raw).unwrap()
+}
+
+/// The bytes each message goes out as.
+fn bytes(messages: &[MidiMessage]) -> Vec<Vec<u8>> {
+    messages
+        .iter()
+        .map(|mm| {
+            let mut buf = vec![0; mm.bytes_size()];
+            mm.copy_to_slice(&mut buf).unwrap();
+            buf
+        })
+        .collect()
+}
+
+fn u7(n: u8) -> U7 {
+    U7::from_u8_lossy(n)
+}
+
+fn u14(n: u16) -> U14 {
+    U14::try_from(n).unwrap()
+}
+
+fn cc(ch: Channel, cc: u8, value: u8) -> MidiMessage<'static> {
+    MidiMessage::ControlChange(ch, ControlFunction(u7(cc)), u7(value))
+}
+
+const CH: Channel = Channel::Ch15;
+
+/// One gamepad, driven the way gilrs and `main` would drive it.
+struct Pad {
+    cfg: Config,
+    state: State,
+    id: GamepadId,
+    /// When every event happens. It stays put, so timed tests can count
+    /// from it exactly.
+    now: Instant,
+}
+
+impl Pad {
+    fn new(cfg: Config) -> Pad {
+        Pad::with_id(cfg, 0)
+    }
+
+    fn with_id(cfg: Config, id: usize) -> Pad {
+        let state = State::new(&cfg);
+        Pad {
+            cfg,
+            state,
+            id: pad(id),
+            now: Instant::now(),
+        }
+    }
+
+    fn send(&mut self, event: EventType) -> Vec<MidiMessage<'static>> {
+        self.send_at(event, SystemTime::now())
+    }
+
+    fn send_at(&mut self, event: EventType, time: SystemTime) -> Vec<MidiMessage<'static>> {
+        let ev = Event {
+            id: self.id,
+            event,
+            time,
+        };
+        map_event(&self.cfg, &mut self.state, &ev, self.now)
+    }
+
+    /// A digital press: gilrs reports the press, then the position.
+    fn press(&mut self, btn: Button) -> Vec<MidiMessage<'static>> {
+        let mut out = self.send(EventType::ButtonPressed(btn, code()));
+        out.extend(self.send(EventType::ButtonChanged(btn, 1.0, code())));
+        out
+    }
+
+    fn release(&mut self, btn: Button) -> Vec<MidiMessage<'static>> {
+        let mut out = self.send(EventType::ButtonReleased(btn, code()));
+        out.extend(self.send(EventType::ButtonChanged(btn, 0.0, code())));
+        out
+    }
+
+    fn analog(&mut self, btn: Button, pos: f32) -> Vec<MidiMessage<'static>> {
+        self.send(EventType::ButtonChanged(btn, pos, code()))
+    }
+
+    fn axis(&mut self, ax: Axis, pos: f32) -> Vec<MidiMessage<'static>> {
+        self.send(EventType::AxisChanged(ax, pos, code()))
+    }
+
+    fn tick(&mut self, now: Instant) -> Vec<MidiMessage<'static>> {
+        let mut out = Vec::new();
+        self.state.tick(&self.cfg, now, &mut out);
+        out
+    }
+
+    /// What actually goes out for `messages` once the per-message limits
+    /// have had their say, like `Output::send_all`.
+    fn outgoing(&mut self, messages: Vec<MidiMessage<'static>>) -> Vec<MidiMessage<'static>> {
+        let mut sent = Vec::new();
+        for mm in messages {
+            if let Some((stolen, mm)) = self.state.outgoing(&self.cfg, mm, self.now) {
+                sent.extend(stolen);
+                sent.push(mm);
+            }
+        }
+        sent
+    }
+}
+
+#[test]
+fn press_and_release_send_a_note() {
+    let mut p = Pad::new(Config::default());
+    let on = p.press(Button::South);
+    assert_eq!(on, vec![MidiMessage::NoteOn(CH, Note::E1, u7(80))]);
+    assert_eq!(bytes(&on), vec![vec![0x9E, 28, 80]]);
+    let off = p.release(Button::South);
+    assert_eq!(off, vec![MidiMessage::NoteOff(CH, Note::E1, u7(64))]);
+    assert_eq!(bytes(&off), vec![vec![0x8E, 28, 64]]);
+}
+
+#[test]
+fn unmapped_buttons_send_nothing() {
+    let mut p = Pad::new(Config::default());
+    assert!(p.press(Button::LeftThumb).is_empty());
+    assert!(p.release(Button::LeftThumb).is_empty());
+}
+
+#[test]
+fn note_off_velocity_is_configurable() {
+    let cfg = Config {
+        note_off_velocity: u7(10),
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    p.press(Button::South);
+    assert_eq!(
+        p.release(Button::South),
+        vec![MidiMessage::NoteOff(CH, Note::E1, u7(10))]
+    );
+}
+
+#[test]
+fn axis_center_and_extremes() {
+    let mut p = Pad::new(Config::default());
+    assert_eq!(p.axis(Axis::LeftStickX, -1.0), vec![cc(CH, 3, 0)]);
+    assert_eq!(p.axis(Axis::LeftStickX, 0.0), vec![cc(CH, 3, 64)]);
+    assert_eq!(p.axis(Axis::LeftStickX, 1.0), vec![cc(CH, 3, 127)]);
+    assert_eq!(
+        bytes(&p.axis(Axis::LeftStickY, 1.0)),
+        vec![vec![0xBE, 4, 127]]
+    );
+}
+
+#[test]
+fn deadzone_snaps_to_exact_center() {
+    let mut p = Pad::new(Config::default());
+    assert_eq!(p.axis(Axis::LeftStickX, 0.05), vec![cc(CH, 3, 64)]);
+    assert_eq!(p.axis(Axis::LeftStickX, -0.05), vec![cc(CH, 3, 64)]);
+}
+
+#[test]
+fn pitch_bend_centers_exactly_inside_the_deadzone() {
+    let mut cfg = Config::default();
+    cfg.axis_pitchbend.insert(Axis::RightStickX);
+    cfg.axis_curves.insert(Axis::RightStickX, Curve::Gamma(0.0));
+    let mut p = Pad::new(cfg);
+    for pos in [0.03, -0.03] {
+        assert_eq!(
+            p.axis(Axis::RightStickX, pos),
+            vec![cc(CH, 5, 64), MidiMessage::PitchBendChange(CH, u14(8192))]
+        );
+    }
+    let shaped = p.cfg.shape_axis(Axis::RightStickX, -0.03);
+    assert!(shaped == 0.0 && shaped.is_sign_positive());
+}
+
+#[test]
+fn deadzone_rescales_the_rest_of_the_range() {
+    let mut p = Pad::new(Config::default());
+    assert_eq!(p.axis(Axis::LeftStickX, 1.0), vec![cc(CH, 3, 127)]);
+    assert_eq!(p.axis(Axis::LeftStickX, 0.54), vec![cc(CH, 3, 96)]);
+}
+
+#[test]
+fn inverted_axes_run_the_other_way() {
+    let mut cfg = Config::default();
+    cfg.invert_axes.insert(Axis::LeftStickX);
+    let mut p = Pad::new(cfg);
+    assert_eq!(p.axis(Axis::LeftStickX, -1.0), vec![cc(CH, 3, 127)]);
+    assert_eq!(p.axis(Axis::LeftStickX, 1.0), vec![cc(CH, 3, 0)]);
+}
+
+#[test]
+fn invert_toggle_flips_every_axis() {
+    let mut cfg = Config::default();
+    cfg.invert_axes.insert(Axis::LeftStickX);
+    cfg.invert_toggle_button = Some(Button::Mode);
+    let mut p = Pad::new(cfg);
+    assert_eq!(p.axis(Axis::LeftStickX, -1.0), vec![cc(CH, 3, 127)]);
+    // Sent again from where it is, and Mode's own note is gone.
+    assert_eq!(p.press(Button::Mode), vec![cc(CH, 3, 0)]);
+    assert_eq!(p.release(Button::Mode), vec![]);
+    assert_eq!(p.axis(Axis::LeftStickX, 1.0), vec![cc(CH, 3, 127)]);
+    assert_eq!(p.axis(Axis::LeftStickY, 1.0), vec![cc(CH, 4, 0)]);
+    let back = p.press(Button::Mode);
+    assert_eq!(back.len(), 2);
+    assert!(back.contains(&cc(CH, 3, 0)) && back.contains(&cc(CH, 4, 127)));
+
+    p.cfg.invert_toggle_resend = false;
+    assert_eq!(p.press(Button::Mode), vec![]);
+    assert_eq!(p.axis(Axis::LeftStickY, 1.0), vec![cc(CH, 4, 0)]);
+}
+
+#[test]
+fn analog_buttons_send_their_pressure() {
+    let mut p = Pad::new(Config::default());
+    assert_eq!(p.analog(Button::LeftTrigger2, 0.0), vec![cc(CH, 1, 0)]);
+    assert_eq!(p.analog(Button::LeftTrigger2, 0.5), vec![cc(CH, 1, 64)]);
+    assert_eq!(p.analog(Button::RightTrigger2, 1.0), vec![cc(CH, 2, 127)]);
+}
+
+#[test]
+fn overshoot_clamps_instead_of_wrapping() {
+    let mut p = Pad::new(Config::default());
+    // Some drivers report a little past the ends.
+    assert_eq!(p.analog(Button::LeftTrigger2, 1.2), vec![cc(CH, 1, 127)]);
+    assert_eq!(p.analog(Button::LeftTrigger2, -0.01), vec![cc(CH, 1, 0)]);
+    assert_eq!(p.axis(Axis::LeftStickX, 1.2), vec![cc(CH, 3, 127)]);
+    assert_eq!(p.axis(Axis::LeftStickX, -1.3), vec![cc(CH, 3, 0)]);
+}
+
+#[test]
+fn conversions_round_to_nearest() {
+    let cfg = Config {
+        deadzone: 0.0,
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    // 63.5 would truncate to 63.
+    assert_eq!(p.analog(Button::LeftTrigger2, 0.5), vec![cc(CH, 1, 64)]);
+    assert_eq!(p.analog(Button::LeftTrigger2, 0.0), vec![cc(CH, 1, 0)]);
+    assert_eq!(p.analog(Button::LeftTrigger2, 1.0), vec![cc(CH, 1, 127)]);
+    // 76.8 would truncate to 76.
+    assert_eq!(p.axis(Axis::LeftStickX, 0.2), vec![cc(CH, 3, 77)]);
+    assert_eq!(p.axis(Axis::LeftStickX, 0.0), vec![cc(CH, 3, 64)]);
+    assert_eq!(p.axis(Axis::LeftStickX, 0.999), vec![cc(CH, 3, 127)]);
+    assert_eq!(p.axis(Axis::LeftStickX, -0.999), vec![cc(CH, 3, 0)]);
+}
+
+#[test]
+fn gamma_two_compresses_the_midpoint() {
+    let mut cfg = Config::default();
+    cfg.button_curves
+        .insert(Button::LeftTrigger2, Curve::Gamma(2.0));
+    let mut p = Pad::new(cfg);
+    // 0.5 squared is 0.25, 31.75 of 127.
+    assert_eq!(p.analog(Button::LeftTrigger2, 0.5), vec![cc(CH, 1, 32)]);
+    assert_eq!(p.analog(Button::LeftTrigger2, 0.0), vec![cc(CH, 1, 0)]);
+    assert_eq!(p.analog(Button::LeftTrigger2, 1.0), vec![cc(CH, 1, 127)]);
+    assert_eq!(p.analog(Button::RightTrigger2, 0.5), vec![cc(CH, 2, 64)]);
+}
+
+#[test]
+fn hires_axes_split_msb_and_lsb() {
+    let mut cfg = Config::default();
+    cfg.hires_axis_ccs.insert(Axis::LeftStickX);
+    let mut p = Pad::new(cfg);
+    assert_eq!(
+        p.axis(Axis::LeftStickX, 0.0),
+        vec![cc(CH, 3, 64), cc(CH, 35, 0)]
+    );
+    assert_eq!(
+        p.axis(Axis::LeftStickX, 1.0),
+        vec![cc(CH, 3, 127), cc(CH, 35, 127)]
+    );
+}
+
+#[test]
+fn nrpn_selects_the_parameter_then_sends_the_data() {
+    let mut cfg = Config::default();
+    cfg.axis_ccs.clear();
+    cfg.axis_nrpn.insert(Axis::LeftStickX, 4660);
+    let mut p = Pad::new(cfg);
+    assert_eq!(
+        bytes(&p.axis(Axis::LeftStickX, 0.0)),
+        vec![
+            vec![0xBE, 0x63, 0x24],
+            vec![0xBE, 0x62, 0x34],
+            vec![0xBE, 0x06, 0x40],
+            vec![0xBE, 0x26, 0x00],
+        ]
+    );
+}
+
+#[test]
+fn axis_range_squeezes_the_cc() {
+    let mut cfg = Config::default();
+    cfg.axis_range.insert(Axis::LeftStickX, (u7(20), u7(100)));
+    let mut p = Pad::new(cfg);
+    assert_eq!(p.axis(Axis::LeftStickX, -1.0), vec![cc(CH, 3, 20)]);
+    assert_eq!(p.axis(Axis::LeftStickX, 0.0), vec![cc(CH, 3, 60)]);
+    assert_eq!(p.axis(Axis::LeftStickX, 1.0), vec![cc(CH, 3, 100)]);
+}
+
+#[test]
+fn axis_center_moves_the_resting_value() {
+    let mut cfg = Config::default();
+    cfg.axis_center.insert(Axis::LeftStickX, u7(0));
+    cfg.axis_center.insert(Axis::LeftStickY, u7(100));
+    let mut p = Pad::new(cfg);
+    assert_eq!(p.axis(Axis::LeftStickX, -1.0), vec![cc(CH, 3, 0)]);
+    assert_eq!(p.axis(Axis::LeftStickX, 0.0), vec![cc(CH, 3, 0)]);
+    assert_eq!(p.axis(Axis::LeftStickX, 1.0), vec![cc(CH, 3, 64)]);
+    assert_eq!(p.axis(Axis::LeftStickY, -1.0), vec![cc(CH, 4, 36)]);
+    assert_eq!(p.axis(Axis::LeftStickY, 0.0), vec![cc(CH, 4, 100)]);
+    assert_eq!(p.axis(Axis::LeftStickY, 1.0), vec![cc(CH, 4, 127)]);
+    // Left unset, it's still 64.
+    assert_eq!(p.axis(Axis::RightStickX, 0.0), vec![cc(CH, 5, 64)]);
+}
+
+#[test]
+fn crossfade_sweeps_two_ccs_against_each_other() {
+    let mut cfg = Config::default();
+    cfg.axis_ccs.clear();
+    cfg.axis_crossfade
+        .insert(Axis::LeftStickX, (u7(20), u7(21)));
+    let mut p = Pad::new(cfg);
+    assert_eq!(
+        p.axis(Axis::LeftStickX, -1.0),
+        vec![cc(CH, 20, 127), cc(CH, 21, 0)]
+    );
+    assert_eq!(
+        p.axis(Axis::LeftStickX, 0.0),
+        vec![cc(CH, 20, 64), cc(CH, 21, 64)]
+    );
+    assert_eq!(
+        p.axis(Axis::LeftStickX, 1.0),
+        vec![cc(CH, 20, 0), cc(CH, 21, 127)]
+    );
+}
+
+#[test]
+fn curve_tables_interpolate_and_keep_the_sign() {
+    let mut cfg = Config::default();
+    let table = [0, 10, 30, 70, 127].iter().map(|n| u7(*n)).collect();
+    cfg.axis_curve_table.insert(Axis::LeftStickX, table);
+    cfg.axis_deadzones.insert(Axis::LeftStickX, 0.0);
+    for (pos, want) in [
+        (0.0, 0.0),
+        (0.125, 5.0),
+        (0.25, 10.0),
+        (0.5, 30.0),
+        (1.0, 127.0),
+    ] {
+        let got = cfg.shape_axis(Axis::LeftStickX, pos) * 127.0;
+        assert!(
+            (got - want).abs() < 1e-3,
+            "{} -> {}, not {}",
+            pos,
+            got,
+            want
+        );
+    }
+    assert!((cfg.shape_axis(Axis::LeftStickX, -0.5) * 127.0 + 30.0).abs() < 1e-3);
+}
+
+#[test]
+fn button_channels_keep_their_notes_on_their_channel() {
+    let mut cfg = Config::default();
+    cfg.button_channels.insert(Button::South, Channel::Ch10);
+    let mut p = Pad::new(cfg);
+    assert_eq!(
+        p.press(Button::South),
+        vec![MidiMessage::NoteOn(Channel::Ch10, Note::E1, u7(80))]
+    );
+    assert_eq!(
+        p.press(Button::East),
+        vec![MidiMessage::NoteOn(CH, Note::D1, u7(80))]
+    );
+    assert_eq!(
+        p.release(Button::South),
+        vec![MidiMessage::NoteOff(Channel::Ch10, Note::E1, u7(64))]
+    );
+}
+
+#[test]
+fn scale_snaps_notes_to_it() {
+    let mut cfg = Config {
+        scale: vec![0, 2, 4, 5, 7, 9, 11],
+        ..Config::default()
+    };
+    cfg.keys.insert(Button::South, Note::Db4);
+    let mut p = Pad::new(cfg);
+    assert_eq!(
+        p.press(Button::South),
+        vec![MidiMessage::NoteOn(CH, Note::C4, u7(80))]
+    );
+}
+
+#[test]
+fn trigger_threshold_has_hysteresis() {
+    let mut cfg = Config::default();
+    cfg.analog_button_ccs.clear();
+    cfg.trigger_note_threshold
+        .insert(Button::LeftTrigger2, (Note::C2, 0.6, 0.4));
+    let mut p = Pad::new(cfg);
+    assert!(p.analog(Button::LeftTrigger2, 0.3).is_empty());
+    assert_eq!(
+        p.analog(Button::LeftTrigger2, 0.65),
+        vec![MidiMessage::NoteOn(CH, Note::C2, u7(80))]
+    );
+    for pos in [0.5, 0.62, 0.45] {
+        assert!(p.analog(Button::LeftTrigger2, pos).is_empty());
+    }
+    assert_eq!(
+        p.analog(Button::LeftTrigger2, 0.39),
+        vec![MidiMessage::NoteOff(CH, Note::C2, u7(64))]
+    );
+}
+
+#[test]
+fn trigger_sends_its_cc_and_one_note() {
+    let mut cfg = Config::default();
+    cfg.keys.insert(Button::RightTrigger2, Note::C4);
+    let mut p = Pad::new(cfg);
+    let mut out = p.analog(Button::RightTrigger2, 0.3);
+    out.extend(p.analog(Button::RightTrigger2, 0.7));
+    out.extend(p.send(EventType::ButtonPressed(Button::RightTrigger2, code())));
+    out.extend(p.analog(Button::RightTrigger2, 0.8));
+    out.extend(p.analog(Button::RightTrigger2, 0.9));
+    assert_eq!(
+        out,
+        vec![
+            cc(CH, 2, 38),
+            cc(CH, 2, 89),
+            MidiMessage::NoteOn(CH, Note::C4, u7(80)),
+            cc(CH, 2, 102),
+            cc(CH, 2, 114),
+        ]
+    );
+}
+
+#[test]
+fn poly_aftertouch_goes_to_the_listed_buttons_notes() {
+    let mut cfg = Config::default();
+    cfg.analog_button_ccs.clear();
+    cfg.poly_aftertouch_buttons.insert(
+        Button::RightTrigger2,
+        vec![Button::South].into_iter().collect(),
+    );
+    let mut p = Pad::new(cfg);
+    assert!(p.analog(Button::RightTrigger2, 0.5).is_empty());
+    p.press(Button::South);
+    p.press(Button::East);
+    assert_eq!(
+        p.analog(Button::RightTrigger2, 0.5),
+        vec![MidiMessage::PolyphonicKeyPressure(CH, Note::E1, u7(64))]
+    );
+}
+
+#[test]
+fn profile_combo_switches_the_mapping() {
+    let mut cfg = Config::default();
+    let mut layer = Layer::default();
+    layer.keys.insert(Button::South, Note::C5);
+    cfg.profiles.push(Profile {
+        name: "leads".to_string(),
+        layer,
+    });
+    cfg.profile_combo = vec![Button::LeftThumb, Button::RightThumb];
+    let mut p = Pad::new(cfg);
+    p.press(Button::LeftThumb);
+    p.press(Button::RightThumb);
+    p.release(Button::LeftThumb);
+    p.release(Button::RightThumb);
+    assert_eq!(
+        p.press(Button::South),
+        vec![MidiMessage::NoteOn(CH, Note::C5, u7(80))]
+    );
+}
+
+#[test]
+fn gamepad_profiles_pick_by_name() {
+    let mut cfg = Config::default();
+    for (name, note) in [("leads", Note::C5), ("drums", Note::C2)] {
+        let mut layer = Layer::default();
+        layer.keys.insert(Button::South, note);
+        cfg.profiles.push(Profile {
+            name: name.to_string(),
+            layer,
+        });
+    }
+    cfg.gamepad_profiles.insert("xbox".into(), "leads".into());
+    cfg.gamepad_profiles
+        .insert("xbox elite".into(), "drums".into());
+    for (id, name, note) in [
+        (0, "Xbox Elite Controller", Note::C2),
+        (1, "Xbox 360 Controller", Note::C5),
+        (2, "8BitDo", Note::E1),
+    ] {
+        let mut p = Pad::with_id(cfg.clone(), id);
+        p.state.name_gamepad(&p.cfg, p.id, name);
+        assert_eq!(
+            p.press(Button::South),
+            vec![MidiMessage::NoteOn(CH, note, u7(80))],
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn debounce_drops_bounces() {
+    let cfg = Config {
+        button_debounce_ms: 20,
+        ..Config::default()
+    };
+    let mut state = State::new(&cfg);
+    let start = Instant::now();
+    let edges = [
+        (0, true),
+        (2, false),
+        (4, true),
+        (6, false),
+        (8, true),
+        (100, false),
+        (103, true),
+        (105, false),
+    ];
+    let kept: Vec<_> = edges
+        .iter()
+        .filter(|(ms, pressed)| {
+            let at = start + Duration::from_millis(*ms);
+            state.debounce(&cfg, (pad(0), Button::South), *pressed, at)
+        })
+        .collect();
+    assert_eq!(kept, vec![&(0, true), &(100, false)]);
+}
+
+#[test]
+fn humanized_velocity_follows_the_seed() {
+    let cfg = Config {
+        velocity_humanize: 10,
+        humanize_seed: Some(7),
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    let mut got = Vec::new();
+    for _ in 0..6 {
+        let on = MidiMessage::NoteOn(CH, Note::C4, u7(100));
+        match p.outgoing(vec![on]).as_slice() {
+            [MidiMessage::NoteOn(_, _, v)] => got.push(u8::from(*v)),
+            other => panic!("{:?}", other),
+        }
+    }
+    // The same every run, each within 10 of 100.
+    assert_eq!(got, vec![110, 96, 99, 95, 98, 106]);
+}
+
+#[test]
+fn humanized_velocity_clamps_to_the_note_on_range() {
+    let cfg = Config {
+        velocity_humanize: 255,
+        humanize_seed: Some(7),
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    let mut got = Vec::new();
+    for velocity in [1, 127] {
+        for _ in 0..4 {
+            let on = MidiMessage::NoteOn(CH, Note::C4, u7(velocity));
+            match p.outgoing(vec![on]).as_slice() {
+                [MidiMessage::NoteOn(_, _, v)] => got.push(u8::from(*v)),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+    // Far past either end, but never off the NoteOn range (or 0, a NoteOff).
+    assert_eq!(got, vec![127, 1, 1, 1, 82, 127, 127, 1]);
+}
+
+#[test]
+fn max_voices_steals_the_oldest() {
+    let cfg = Config {
+        max_voices: Some(2),
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    let mut out = Vec::new();
+    for btn in [Button::North, Button::East, Button::South] {
+        let on = p.press(btn);
+        out.extend(p.outgoing(on));
+    }
+    assert_eq!(
+        out,
+        vec![
+            MidiMessage::NoteOn(CH, Note::C1, u7(80)),
+            MidiMessage::NoteOn(CH, Note::D1, u7(80)),
+            MidiMessage::NoteOff(CH, Note::C1, u7(64)),
+            MidiMessage::NoteOn(CH, Note::E1, u7(80)),
+        ]
+    );
+    // It's already been stopped.
+    let off = p.release(Button::North);
+    assert!(p.outgoing(off).is_empty());
+}
+
+#[test]
+fn mono_goes_back_to_the_previous_note() {
+    let cfg = Config {
+        mono: true,
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    p.press(Button::North);
+    p.press(Button::East);
+    p.press(Button::South);
+    assert!(p.release(Button::East).is_empty());
+    assert_eq!(
+        p.release(Button::South),
+        vec![
+            MidiMessage::NoteOff(CH, Note::E1, u7(64)),
+            MidiMessage::NoteOn(CH, Note::C1, u7(80)),
+        ]
+    );
+    assert_eq!(
+        p.release(Button::North),
+        vec![MidiMessage::NoteOff(CH, Note::C1, u7(64))]
+    );
+}
+
+#[test]
+fn cc_rate_limit_sends_the_latest_later() {
+    let cfg = Config {
+        cc_rate_limit: Some(10),
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    let first = p.axis(Axis::LeftStickX, 1.0);
+    assert_eq!(p.outgoing(first), vec![cc(CH, 3, 127)]);
+    let second = p.axis(Axis::LeftStickX, -1.0);
+    assert!(p.outgoing(second).is_empty());
+    let later = p.tick(p.now + Duration::from_millis(150));
+    assert_eq!(later, vec![cc(CH, 3, 0)]);
+}
+
+#[test]
+fn latching_buttons_toggle() {
+    let mut cfg = Config::default();
+    cfg.latching_buttons.insert(Button::South);
+    let mut p = Pad::new(cfg);
+    let start = p.now;
+    assert_eq!(
+        p.press(Button::South),
+        vec![MidiMessage::NoteOn(CH, Note::E1, u7(80))]
+    );
+    assert!(p.release(Button::South).is_empty());
+    // Still sounding, with nothing left to stop it but the next press.
+    assert!(p.tick(start + Duration::from_secs(1)).is_empty());
+    assert_eq!(
+        p.press(Button::South),
+        vec![MidiMessage::NoteOff(CH, Note::E1, u7(64))]
+    );
+    assert!(p.release(Button::South).is_empty());
+    assert_eq!(
+        p.press(Button::South),
+        vec![MidiMessage::NoteOn(CH, Note::E1, u7(80))]
+    );
+}
+
+#[test]
+fn arpeggio_steps_through_the_chord_on_tick() {
+    let mut cfg = Config {
+        arp_bpm: 120.0,
+        arp_division: 4,
+        ..Config::default()
+    };
+    cfg.chords
+        .insert(Button::South, vec![Note::C4, Note::E4, Note::G4]);
+    cfg.arp_buttons.insert(Button::South);
+    let mut p = Pad::new(cfg);
+    let start = p.now;
+    let ms = |n| start + Duration::from_millis(n);
+    // Nothing until the first step, and sixteenths at 120bpm after that.
+    assert!(p.press(Button::South).is_empty());
+    assert_eq!(
+        p.tick(ms(0)),
+        vec![MidiMessage::NoteOn(CH, Note::C4, u7(80))]
+    );
+    assert!(p.tick(ms(100)).is_empty());
+    assert_eq!(
+        p.tick(ms(125)),
+        vec![
+            MidiMessage::NoteOff(CH, Note::C4, u7(64)),
+            MidiMessage::NoteOn(CH, Note::E4, u7(80)),
+        ]
+    );
+    assert_eq!(
+        p.tick(ms(250)),
+        vec![
+            MidiMessage::NoteOff(CH, Note::E4, u7(64)),
+            MidiMessage::NoteOn(CH, Note::G4, u7(80)),
+        ]
+    );
+    // And round again.
+    assert_eq!(
+        p.tick(ms(375)),
+        vec![
+            MidiMessage::NoteOff(CH, Note::G4, u7(64)),
+            MidiMessage::NoteOn(CH, Note::C4, u7(80)),
+        ]
+    );
+    assert_eq!(
+        p.release(Button::South),
+        vec![MidiMessage::NoteOff(CH, Note::C4, u7(64))]
+    );
+    assert!(p.tick(ms(1000)).is_empty());
+}
+
+#[test]
+fn drum_hits_stop_after_the_gate() {
+    let mut cfg = Config {
+        gate_ms: 50,
+        ..Config::default()
+    };
+    cfg.drum_buttons.insert(Button::South);
+    let mut p = Pad::new(cfg);
+    let start = p.now;
+    let ms = |n| start + Duration::from_millis(n);
+    assert_eq!(
+        p.press(Button::South),
+        vec![MidiMessage::NoteOn(CH, Note::E1, u7(80))]
+    );
+    // Letting go doesn't cut it short.
+    assert!(p.release(Button::South).is_empty());
+    assert!(p.tick(ms(49)).is_empty());
+    assert_eq!(
+        p.tick(ms(50)),
+        vec![MidiMessage::NoteOff(CH, Note::E1, u7(64))]
+    );
+    assert!(p.tick(ms(100)).is_empty());
+
+    // A hit inside the gate stops the one before it first, and gets a
+    // gate of its own.
+    p.now = ms(200);
+    p.press(Button::South);
+    p.release(Button::South);
+    p.now = ms(230);
+    assert_eq!(
+        p.press(Button::South),
+        vec![
+            MidiMessage::NoteOff(CH, Note::E1, u7(64)),
+            MidiMessage::NoteOn(CH, Note::E1, u7(80)),
+        ]
+    );
+    assert!(p.tick(ms(260)).is_empty());
+    assert_eq!(
+        p.tick(ms(280)),
+        vec![MidiMessage::NoteOff(CH, Note::E1, u7(64))]
+    );
+}
+
+#[test]
+fn smoothed_axes_ease_towards_the_stick_on_tick() {
+    let mut cfg = Config::default();
+    cfg.axis_smoothing.insert(Axis::LeftStickX, 100.0);
+    let mut p = Pad::new(cfg);
+    let start = p.now;
+    let ms = |n| start + Duration::from_millis(n);
+    // The move itself sends nothing, `tick` takes it from there.
+    assert!(p.axis(Axis::LeftStickX, 1.0).is_empty());
+    // One time constant covers about two thirds of the way.
+    assert_eq!(p.tick(ms(100)), vec![cc(CH, 3, 104)]);
+    assert_eq!(p.tick(ms(200)), vec![cc(CH, 3, 119)]);
+    assert_eq!(p.tick(ms(1000)), vec![cc(CH, 3, 127)]);
+    // Once there, it stays put.
+    assert!(p.tick(ms(1100)).is_empty());
+}
+
+#[test]
+fn strum_staggers_the_chord() {
+    let mut cfg = Config::default();
+    cfg.chords
+        .insert(Button::South, vec![Note::C4, Note::E4, Note::G4]);
+    cfg.strum_buttons.insert(Button::South);
+    cfg.strum_down_button = Some(Button::LeftTrigger);
+    let mut p = Pad::new(cfg);
+    let start = p.now;
+    assert_eq!(
+        p.press(Button::South),
+        vec![MidiMessage::NoteOn(CH, Note::C4, u7(80))]
+    );
+    assert_eq!(
+        p.tick(start + Duration::from_millis(20)),
+        vec![MidiMessage::NoteOn(CH, Note::E4, u7(80))]
+    );
+    assert_eq!(
+        p.tick(start + Duration::from_millis(35)),
+        vec![MidiMessage::NoteOn(CH, Note::G4, u7(80))]
+    );
+    assert_eq!(p.release(Button::South).len(), 3);
+
+    // Held down, high to low.
+    p.press(Button::LeftTrigger);
+    assert_eq!(
+        p.press(Button::South),
+        vec![MidiMessage::NoteOn(CH, Note::G4, u7(80))]
+    );
+}
+
+#[test]
+fn releasing_mid_strum_stops_only_what_started() {
+    let mut cfg = Config::default();
+    cfg.chords
+        .insert(Button::South, vec![Note::C4, Note::E4, Note::G4]);
+    cfg.strum_buttons.insert(Button::South);
+    let mut p = Pad::new(cfg);
+    p.press(Button::South);
+    assert_eq!(
+        p.release(Button::South),
+        vec![MidiMessage::NoteOff(CH, Note::C4, u7(64))]
+    );
+    assert!(p.tick(p.now + Duration::from_secs(1)).is_empty());
+}
+
+#[test]
+fn note_repeat_retriggers_while_held() {
+    let mut cfg = Config::default();
+    cfg.note_repeat_buttons.insert(Button::West, 100);
+    let mut p = Pad::new(cfg);
+    let start = p.now;
+    p.press(Button::West);
+    let pair = vec![
+        MidiMessage::NoteOff(CH, Note::F1, u7(64)),
+        MidiMessage::NoteOn(CH, Note::F1, u7(80)),
+    ];
+    assert!(p.tick(start).is_empty());
+    assert_eq!(p.tick(start + Duration::from_millis(110)), pair);
+    // Missed repeats are skipped, not piled up.
+    assert_eq!(p.tick(start + Duration::from_millis(450)), pair);
+    assert_eq!(
+        p.release(Button::West),
+        vec![MidiMessage::NoteOff(CH, Note::F1, u7(64))]
+    );
+    assert!(p.tick(start + Duration::from_secs(1)).is_empty());
+}
+
+#[test]
+fn combo_notes_need_every_button() {
+    let mut cfg = Config::default();
+    cfg.keys.clear();
+    cfg.combo_notes = vec![
+        ComboNote {
+            buttons: vec![Button::North, Button::East],
+            note: Note::C4,
+        },
+        ComboNote {
+            buttons: vec![Button::North, Button::East, Button::South],
+            note: Note::E4,
+        },
+    ];
+    let mut p = Pad::new(cfg);
+    assert!(p.press(Button::North).is_empty());
+    assert_eq!(
+        p.press(Button::East),
+        vec![MidiMessage::NoteOn(CH, Note::C4, u7(80))]
+    );
+    // The bigger combo takes over.
+    assert_eq!(
+        p.press(Button::South),
+        vec![
+            MidiMessage::NoteOff(CH, Note::C4, u7(64)),
+            MidiMessage::NoteOn(CH, Note::E4, u7(80)),
+        ]
+    );
+    assert_eq!(
+        p.release(Button::South),
+        vec![MidiMessage::NoteOff(CH, Note::E4, u7(64))]
+    );
+    assert!(p.release(Button::East).is_empty());
+}
+
+#[test]
+fn panic_stops_everything() {
+    let cfg = Config {
+        panic_combo: vec![Button::LeftThumb, Button::RightThumb],
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    let on = p.press(Button::South);
+    p.outgoing(on);
+    assert!(p.press(Button::LeftThumb).is_empty());
+    assert_eq!(
+        p.press(Button::RightThumb),
+        vec![
+            MidiMessage::NoteOff(CH, Note::E1, u7(64)),
+            cc(CH, 64, 0),
+            MidiMessage::PitchBendChange(CH, u14(8192)),
+            cc(CH, 120, 0),
+            cc(CH, 123, 0),
+        ]
+    );
+    assert!(p.release(Button::South).is_empty());
+}
+
+#[test]
+fn release_velocity_from_speed() {
+    let cfg = Config {
+        release_velocity_from_speed: true,
+        note_off_velocity: u7(40),
+        keys: vec![(Button::RightTrigger2, Note::C4), (Button::South, Note::E1)]
+            .into_iter()
+            .collect(),
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    let t0 = SystemTime::now();
+    let at = |ms| t0 + Duration::from_millis(ms);
+    p.send_at(
+        EventType::ButtonPressed(Button::RightTrigger2, code()),
+        at(0),
+    );
+    p.send_at(
+        EventType::ButtonChanged(Button::RightTrigger2, 1.0, code()),
+        at(0),
+    );
+    p.send_at(
+        EventType::ButtonChanged(Button::RightTrigger2, 0.9, code()),
+        at(10),
+    );
+    p.send_at(
+        EventType::ButtonChanged(Button::RightTrigger2, 0.8, code()),
+        at(40),
+    );
+    // 0.2 in 40ms, a quarter of full speed.
+    assert_eq!(
+        p.send_at(
+            EventType::ButtonReleased(Button::RightTrigger2, code()),
+            at(45)
+        ),
+        vec![MidiMessage::NoteOff(CH, Note::C4, u7(32))]
+    );
+    // Digital buttons have no speed to go by.
+    p.press(Button::South);
+    assert_eq!(
+        p.release(Button::South),
+        vec![MidiMessage::NoteOff(CH, Note::E1, u7(40))]
+    );
+    // Faster than full speed tops out.
+    p.send_at(
+        EventType::ButtonPressed(Button::RightTrigger2, code()),
+        at(100),
+    );
+    for (pos, ms) in [(1.0, 100), (0.5, 110)] {
+        p.send_at(
+            EventType::ButtonChanged(Button::RightTrigger2, pos, code()),
+            at(ms),
+        );
+    }
+    assert_eq!(
+        p.send_at(
+            EventType::ButtonReleased(Button::RightTrigger2, code()),
+            at(112)
+        ),
+        vec![MidiMessage::NoteOff(CH, Note::C4, u7(127))]
+    );
+}
+
+#[test]
+fn raw_codes_map_unnamed_axes() {
+    let mut cfg = Config::default();
+    cfg.raw_axis_ccs.insert(code().into_u32(), u7(12));
+    let mut p = Pad::new(cfg);
+    assert_eq!(p.axis(Axis::Unknown, 1.0), vec![cc(CH, 12, 127)]);
+
+    let mut p = Pad::new(Config::default());
+    assert!(p.axis(Axis::Unknown, 1.0).is_empty());
+}
+
+#[test]
+fn raw_codes_map_unnamed_buttons() {
+    let mut cfg = Config::default();
+    cfg.raw_button_notes.insert(code().into_u32(), Note::C3);
+    let mut p = Pad::new(cfg);
+    assert_eq!(
+        p.send(EventType::ButtonPressed(Button::Unknown, code())),
+        vec![MidiMessage::NoteOn(CH, Note::C3, u7(80))]
+    );
+    assert_eq!(
+        p.send(EventType::ButtonReleased(Button::Unknown, code())),
+        vec![MidiMessage::NoteOff(CH, Note::C3, u7(64))]
+    );
+
+    let mut p = Pad::new(Config::default());
+    assert!(p
+        .send(EventType::ButtonPressed(Button::Unknown, code()))
+        .is_empty());
+}
+
+#[test]
+fn catchall_cc_only_takes_unmapped_buttons() {
+    let raw = code().into_u32();
+    let mut cfg = Config {
+        catchall_button_cc_base: Some(u7(20)),
+        ..Config::default()
+    };
+    cfg.raw_button_notes.insert(raw + 1, Note::C3);
+    let mut p = Pad::new(cfg);
+    let want = 20 + (raw % 100) as u8;
+    assert_eq!(p.press(Button::LeftThumb), vec![cc(CH, want, 127)]);
+    assert_eq!(p.release(Button::LeftThumb), vec![cc(CH, want, 0)]);
+    assert_eq!(
+        p.send(EventType::ButtonPressed(Button::Unknown, code())),
+        vec![cc(CH, want, 127)]
+    );
+    assert_eq!(
+        p.press(Button::South),
+        vec![MidiMessage::NoteOn(CH, Note::E1, u7(80))]
+    );
+    // Analog buttons already have their CCs.
+    assert_eq!(
+        p.send(EventType::ButtonPressed(Button::LeftTrigger2, code())),
+        vec![]
+    );
+}
+
+#[test]
+fn program_bank_sends_bank_select_then_program() {
+    let mut cfg = Config::default();
+    cfg.program_bank.insert(Button::West, (u7(1), u7(2), u7(3)));
+    cfg.program_buttons
+        .insert(Button::North, ProgramButton::Step(ProgramStep::Next));
+    let mut p = Pad::new(cfg);
+    assert_eq!(
+        p.press(Button::West),
+        vec![
+            cc(CH, 0, 1),
+            cc(CH, 32, 2),
+            MidiMessage::ProgramChange(CH, u7(3))
+        ]
+    );
+    assert_eq!(p.release(Button::West), vec![]);
+    assert_eq!(
+        p.press(Button::North),
+        vec![MidiMessage::ProgramChange(CH, u7(4))]
+    );
+}
+
+#[test]
+fn raw_button_bytes_go_out_as_they_are_on_press() {
+    let mut cfg = Config::default();
+    cfg.raw_button_bytes.insert(Button::West, vec![0xB0, 7]);
+    let mut p = Pad::new(cfg);
+    assert_eq!(p.press(Button::West), vec![]);
+    assert_eq!(p.state.take_raw(), vec![vec![0xB0, 7]]);
+    assert_eq!(p.release(Button::West), vec![]);
+    assert!(p.state.take_raw().is_empty());
+
+    assert!(is_midi(&[0xB0, 7, 100, 0xC0, 3]));
+    assert!(is_midi(&[0xF0, 0x7E, 0xF7]));
+    assert!(!is_midi(&[0xB0, 7]));
+    assert!(!is_midi(&[7, 100]));
+    assert!(!is_midi(&[]));
+}
+
+#[test]
+fn transport_buttons_fire_on_press() {
+    let cfg = Config {
+        transport_buttons: vec![
+            (Button::Start, Transport::Start),
+            (Button::Select, Transport::Stop),
+            (Button::Mode, Transport::Continue),
+        ]
+        .into_iter()
+        .collect(),
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    assert_eq!(p.press(Button::Start), vec![MidiMessage::Start]);
+    assert!(p.release(Button::Start).is_empty());
+    assert_eq!(p.press(Button::Select), vec![MidiMessage::Stop]);
+    assert_eq!(p.press(Button::Mode), vec![MidiMessage::Continue]);
+}
+
+#[test]
+fn song_position_is_rate_limited() {
+    let cfg = Config {
+        song_position_axis: Some(Axis::RightZ),
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    assert_eq!(
+        p.axis(Axis::RightZ, 1.0),
+        vec![MidiMessage::SongPositionPointer(u14(16383))]
+    );
+    assert!(p.axis(Axis::RightZ, -1.0).is_empty());
+    assert_eq!(
+        p.tick(p.now + Duration::from_millis(60)),
+        vec![MidiMessage::SongPositionPointer(u14(0))]
+    );
+}
+
+#[test]
+fn gamepads_go_on_their_own_channels() {
+    let cfg = Config {
+        gamepad_channels: vec![Channel::Ch1, Channel::Ch2],
+        ..Config::default()
+    };
+    let mut state = State::new(&cfg);
+    let mut out = Vec::new();
+    for id in [0, 1] {
+        let ev = Event::new(pad(id), EventType::ButtonPressed(Button::South, code()));
+        out.extend(map_event(&cfg, &mut state, &ev, Instant::now()));
+    }
+    assert_eq!(
+        out,
+        vec![
+            MidiMessage::NoteOn(Channel::Ch1, Note::E1, u7(80)),
+            MidiMessage::NoteOn(Channel::Ch2, Note::E1, u7(80)),
+        ]
+    );
+    let ev = Event::new(pad(1), EventType::Disconnected);
+    assert_eq!(
+        map_event(&cfg, &mut state, &ev, Instant::now()),
+        vec![MidiMessage::NoteOff(Channel::Ch2, Note::E1, u7(64))]
+    );
+}
+
+#[test]
+fn disconnecting_drops_a_press_still_waiting_on_its_position() {
+    let cfg = Config {
+        velocity_mode: VelocityMode::FromPressure,
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    assert!(p
+        .send(EventType::ButtonPressed(Button::South, code()))
+        .is_empty());
+    assert!(p.send(EventType::Disconnected).is_empty());
+    p.send(EventType::Connected);
+    assert!(p
+        .send(EventType::ButtonChanged(Button::South, 1.0, code()))
+        .is_empty());
+}
+
+#[test]
+fn channel_rotation_deals_notes_out_in_turn() {
+    let cfg = Config {
+        channel_rotation: Some(vec![Channel::Ch1, Channel::Ch2, Channel::Ch3, Channel::Ch4]),
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    let buttons = [
+        Button::North,
+        Button::East,
+        Button::South,
+        Button::West,
+        Button::LeftTrigger,
+    ];
+    let mut ons = Vec::new();
+    for btn in buttons.iter() {
+        let on = p.press(*btn);
+        ons.extend(p.outgoing(on));
+    }
+    assert_eq!(
+        ons,
+        vec![
+            MidiMessage::NoteOn(Channel::Ch1, Note::C1, u7(80)),
+            MidiMessage::NoteOn(Channel::Ch2, Note::D1, u7(80)),
+            MidiMessage::NoteOn(Channel::Ch3, Note::E1, u7(80)),
+            MidiMessage::NoteOn(Channel::Ch4, Note::F1, u7(80)),
+            MidiMessage::NoteOn(Channel::Ch1, Note::A2, u7(80)),
+        ]
+    );
+    let mut offs = Vec::new();
+    for btn in buttons.iter().rev() {
+        let off = p.release(*btn);
+        offs.extend(p.outgoing(off));
+    }
+    assert_eq!(
+        offs,
+        vec![
+            MidiMessage::NoteOff(Channel::Ch1, Note::A2, u7(64)),
+            MidiMessage::NoteOff(Channel::Ch4, Note::F1, u7(64)),
+            MidiMessage::NoteOff(Channel::Ch3, Note::E1, u7(64)),
+            MidiMessage::NoteOff(Channel::Ch2, Note::D1, u7(64)),
+            MidiMessage::NoteOff(Channel::Ch1, Note::C1, u7(64)),
+        ]
+    );
+}
+
+#[test]
+fn channel_rotation_stops_a_repeated_note_where_each_started() {
+    let mut cfg = Config {
+        channel_rotation: Some(vec![Channel::Ch1, Channel::Ch2]),
+        ..Config::default()
+    };
+    cfg.keys.insert(Button::North, Note::E1);
+    let mut p = Pad::new(cfg);
+    let on = p.press(Button::South);
+    p.outgoing(on);
+    let on = p.press(Button::North);
+    assert_eq!(
+        p.outgoing(on),
+        vec![MidiMessage::NoteOn(Channel::Ch2, Note::E1, u7(80))]
+    );
+    let off = p.release(Button::South);
+    assert_eq!(
+        p.outgoing(off),
+        vec![MidiMessage::NoteOff(Channel::Ch1, Note::E1, u7(64))]
+    );
+    let off = p.release(Button::North);
+    assert_eq!(
+        p.outgoing(off),
+        vec![MidiMessage::NoteOff(Channel::Ch2, Note::E1, u7(64))]
+    );
+}
+
+#[test]
+fn mpe_gives_each_note_its_own_channel_and_expression() {
+    let mut cfg = Config {
+        mpe: true,
+        mpe_channels: vec![Channel::Ch2, Channel::Ch3],
+        ..Config::default()
+    };
+    cfg.axis_ccs.clear();
+    cfg.analog_button_ccs.clear();
+    cfg.axis_pitchbend.insert(Axis::RightStickX);
+    cfg.analog_button_aftertouch.insert(Button::RightTrigger2);
+    let mut p = Pad::new(cfg);
+    let bend = |ch, v| MidiMessage::PitchBendChange(ch, u14(v));
+
+    // Nothing for it to bend yet.
+    let out = p.axis(Axis::RightStickX, 1.0);
+    assert!(p.outgoing(out).is_empty());
+    let out = p.axis(Axis::RightStickX, 0.0);
+    assert!(p.outgoing(out).is_empty());
+
+    let out = p.press(Button::South);
+    assert_eq!(
+        p.outgoing(out),
+        vec![MidiMessage::NoteOn(Channel::Ch2, Note::E1, u7(80))]
+    );
+    let out = p.axis(Axis::RightStickX, 1.0);
+    assert_eq!(p.outgoing(out), vec![bend(Channel::Ch2, 16383)]);
+    let out = p.press(Button::East);
+    assert_eq!(
+        p.outgoing(out),
+        vec![MidiMessage::NoteOn(Channel::Ch3, Note::D1, u7(80))]
+    );
+    // Only the newest note follows the stick.
+    let out = p.axis(Axis::RightStickX, -1.0);
+    assert_eq!(p.outgoing(out), vec![bend(Channel::Ch3, 0)]);
+    let out = p.analog(Button::RightTrigger2, 0.5);
+    assert_eq!(
+        p.outgoing(out),
+        vec![MidiMessage::ChannelPressure(Channel::Ch3, u7(64))]
+    );
+
+    let out = p.release(Button::South);
+    assert_eq!(
+        p.outgoing(out),
+        vec![MidiMessage::NoteOff(Channel::Ch2, Note::E1, u7(64))]
+    );
+    // The channel comes back unbent for the next note.
+    let out = p.press(Button::West);
+    assert_eq!(
+        p.outgoing(out),
+        vec![
+            bend(Channel::Ch2, 8192),
+            MidiMessage::NoteOn(Channel::Ch2, Note::F1, u7(80)),
+        ]
+    );
+    // Out of channels, the oldest note makes room.
+    let out = p.press(Button::North);
+    assert_eq!(
+        p.outgoing(out),
+        vec![
+            MidiMessage::NoteOff(Channel::Ch3, Note::D1, u7(64)),
+            bend(Channel::Ch3, 8192),
+            MidiMessage::NoteOn(Channel::Ch3, Note::C1, u7(80)),
+        ]
+    );
+    let out = p.release(Button::East);
+    assert!(p.outgoing(out).is_empty());
+    let out = p.release(Button::North);
+    assert_eq!(
+        p.outgoing(out),
+        vec![MidiMessage::NoteOff(Channel::Ch3, Note::C1, u7(64))]
+    );
+}
+
+#[test]
+fn repeats_retrigger_only_the_listed_buttons() {
+    let mut cfg = Config::default();
+    cfg.repeat_as_retrigger.insert(Button::South);
+    let mut p = Pad::new(cfg);
+    let repeat = |btn| EventType::ButtonRepeated(btn, code());
+    assert!(p.send(repeat(Button::South)).is_empty());
+    p.press(Button::South);
+    p.press(Button::East);
+    let pair = vec![
+        MidiMessage::NoteOff(CH, Note::E1, u7(64)),
+        MidiMessage::NoteOn(CH, Note::E1, u7(80)),
+    ];
+    assert_eq!(p.send(repeat(Button::South)), pair);
+    assert_eq!(p.send(repeat(Button::South)), pair);
+    assert!(p.send(repeat(Button::East)).is_empty());
+    assert_eq!(
+        p.release(Button::South),
+        vec![MidiMessage::NoteOff(CH, Note::E1, u7(64))]
+    );
+    assert!(p.send(repeat(Button::South)).is_empty());
+}
+
+#[test]
+fn button_velocity_overrides_the_global_one() {
+    let mut cfg = Config::default();
+    cfg.button_velocity.insert(Button::South, u7(120));
+    let mut p = Pad::new(cfg);
+    assert_eq!(
+        p.press(Button::South),
+        vec![MidiMessage::NoteOn(CH, Note::E1, u7(120))]
+    );
+    assert_eq!(
+        p.press(Button::North),
+        vec![MidiMessage::NoteOn(CH, Note::C1, u7(80))]
+    );
+}
+
+#[test]
+fn modifier_notes_pick_by_depth_and_stop_what_they_picked() {
+    let mut cfg = Config::default();
+    cfg.analog_button_ccs.clear();
+    let notes = vec![Note::C2, Note::C3, Note::C4];
+    cfg.modifier_notes.insert(
+        Button::South,
+        ModifierNotes {
+            notes: notes.clone(),
+            button: Some(Button::LeftTrigger2),
+            axis: None,
+        },
+    );
+    cfg.modifier_notes.insert(
+        Button::East,
+        ModifierNotes {
+            notes,
+            button: None,
+            axis: Some(Axis::LeftZ),
+        },
+    );
+    let mut p = Pad::new(cfg);
+    let on = |note| vec![MidiMessage::NoteOn(CH, note, u7(80))];
+    let off = |note| vec![MidiMessage::NoteOff(CH, note, u7(64))];
+
+    // Never touched, it's as if released.
+    assert_eq!(p.press(Button::South), on(Note::C2));
+    p.release(Button::South);
+    p.analog(Button::LeftTrigger2, 0.5);
+    assert_eq!(p.press(Button::South), on(Note::C3));
+    p.analog(Button::LeftTrigger2, 1.0);
+    assert_eq!(p.release(Button::South), off(Note::C3));
+    assert_eq!(p.press(Button::South), on(Note::C4));
+
+    p.axis(Axis::LeftZ, -0.9);
+    assert_eq!(p.press(Button::East), on(Note::C2));
+    p.axis(Axis::LeftZ, 0.9);
+    assert_eq!(p.release(Button::East), off(Note::C2));
+    assert_eq!(p.press(Button::East), on(Note::C4));
+}
+
+#[test]
+fn on_connect_goes_out_in_order_with_its_delays() {
+    let cfg = Config {
+        on_connect: vec![
+            MacroStep {
+                program: Some(u7(5)),
+                ..MacroStep::default()
+            },
+            MacroStep {
+                cc: Some((u7(7), u7(100))),
+                channel: Some(Channel::Ch2),
+                ..MacroStep::default()
+            },
+            MacroStep {
+                delay_ms: 100,
+                bend: Some(8192),
+                ..MacroStep::default()
+            },
+        ],
+        ..Config::default()
+    };
+    let mut p = Pad::new(cfg);
+    let start = p.now;
+    p.state.on_connect(&p.cfg, start);
+    assert_eq!(
+        p.tick(start),
+        vec![
+            MidiMessage::ProgramChange(CH, u7(5)),
+            cc(Channel::Ch2, 7, 100),
+        ]
+    );
+    assert!(p.tick(start + Duration::from_millis(50)).is_empty());
+    assert_eq!(
+        p.tick(start + Duration::from_millis(100)),
+        vec![MidiMessage::PitchBendChange(CH, u14(8192))]
+    );
+    assert!(p.tick(start + Duration::from_secs(1)).is_empty());
+}
+
+#[test]
+fn timed_output_counts_from_the_event_not_the_read() {
+    let mut cfg = Config::default();
+    cfg.chords
+        .insert(Button::South, vec![Note::C4, Note::E4, Note::G4]);
+    cfg.strum_buttons.insert(Button::South);
+    cfg.note_repeat_buttons.insert(Button::West, 100);
+    let mut p = Pad::new(cfg);
+    // Events placed well away from when they get read.
+    p.now += Duration::from_secs(60);
+    p.press(Button::South);
+    p.press(Button::West);
+    assert!(p.tick(p.now + Duration::from_millis(5)).is_empty());
+    assert_eq!(
+        p.tick(p.now + Duration::from_millis(20)),
+        vec![MidiMessage::NoteOn(CH, Note::E4, u7(80))]
+    );
+    assert_eq!(
+        p.tick(p.now + Duration::from_millis(100)),
+        vec![
+            MidiMessage::NoteOff(CH, Note::F1, u7(64)),
+            MidiMessage::NoteOn(CH, Note::F1, u7(80)),
+            MidiMessage::NoteOn(CH, Note::G4, u7(80)),
+        ]
+    );
+}
@@ -0,0 +1,72 @@
+//! What `--record` leaves behind, read back the way a sequencer would.
+
+use gamepad2midi::smf::Recorder;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+use wmidi::MidiMessage;
+
+/// A variable-length quantity from the front of `bytes`.
+fn varint(bytes: &mut &[u8]) -> u32 {
+    let mut v = 0;
+    loop {
+        let b = bytes[0];
+        *bytes = &bytes[1..];
+        v = v << 7 | u32::from(b & 0x7F);
+        if b & 0x80 == 0 {
+            return v;
+        }
+    }
+}
+
+/// The (delta, bytes) of every MIDI event in the file at `path`, skipping
+/// the meta events.
+fn events(path: &std::path::Path) -> Vec<(u32, Vec<u8>)> {
+    let file = std::fs::read(path).unwrap();
+    assert_eq!(&file[..4], b"MThd");
+    assert_eq!(&file[14..18], b"MTrk");
+    let len = u32::from_be_bytes([file[18], file[19], file[20], file[21]]) as usize;
+    let mut track = &file[22..];
+    assert_eq!(track.len(), len);
+    let mut out = Vec::new();
+    while !track.is_empty() {
+        let delta = varint(&mut track);
+        if track[0] == 0xFF {
+            track = &track[2..];
+            let size = varint(&mut track) as usize;
+            track = &track[size..];
+            continue;
+        }
+        let size = MidiMessage::try_from(track).unwrap().bytes_size();
+        out.push((delta, track[..size].to_vec()));
+        track = &track[size..];
+    }
+    out
+}
+
+#[test]
+fn back_to_back_messages_each_get_a_delta() {
+    let path = std::env::temp_dir().join(format!("gamepad2midi-test-{}.mid", std::process::id()));
+    let start = Instant::now();
+    let mut recorder = Recorder::new(&path);
+    // What `raw_button_bytes = { South = [0xB0, 7, 100, 0xC0, 3] }` sends.
+    recorder.record(
+        &[0xB0, 0x07, 0x64, 0xC0, 0x03],
+        start + Duration::from_secs(1),
+    );
+    recorder.record(&[0x90, 0x3C, 0x50], start + Duration::from_secs(2));
+    drop(recorder);
+    let events = events(&path);
+    std::fs::remove_file(&path).unwrap();
+    let bytes: Vec<_> = events.iter().map(|(_, b)| b.clone()).collect();
+    assert_eq!(
+        bytes,
+        vec![
+            vec![0xB0, 0x07, 0x64],
+            vec![0xC0, 0x03],
+            vec![0x90, 0x3C, 0x50]
+        ]
+    );
+    // The second message goes out along with the first.
+    assert_eq!(events[1].0, 0);
+    assert!((990..=1000).contains(&events[2].0), "{:?}", events);
+}